@@ -219,6 +219,14 @@ impl NetworkStateInfo for TestNetwork {
 	fn external_addresses(&self) -> Vec<Multiaddr> {
 		self.external_addresses.clone()
 	}
+
+	fn listen_addresses(&self) -> Vec<Multiaddr> {
+		Vec::new()
+	}
+
+	fn num_connected_peers(&self) -> usize {
+		0
+	}
 }
 
 #[test]
@@ -22,6 +22,7 @@ use sp_consensus::{Error as ConsensusError, ImportResult};
 use sp_blockchain::Error as BlockchainError;
 use sp_inherents::Error as InherentsError;
 use futures::channel::{oneshot, mpsc::SendError};
+use std::time::Duration;
 
 /// Error code for rpc
 mod codes {
@@ -32,7 +33,12 @@ mod codes {
 	pub const CONSENSUS_ERROR: i64 = 14_000;
 	pub const INHERENTS_ERROR: i64 = 15_000;
 	pub const BLOCKCHAIN_ERROR: i64 = 16_000;
+	pub const TOO_SOON: i64 = 17_000;
+	pub const TOO_MANY_EXTRINSICS: i64 = 18_000;
+	pub const SEAL_REFUSED: i64 = 19_000;
 	pub const UNKNOWN_ERROR: i64 = 20_000;
+	pub const INVALID_INHERENTS: i64 = 21_000;
+	pub const TRANSACTION_NOT_INCLUDED: i64 = 22_000;
 }
 
 /// errors encountered by background block authorship task
@@ -58,6 +64,33 @@ pub enum Error {
 	#[display(fmt = "Supplied parent_hash: {} doesn't exist in chain", _0)]
 	#[from(ignore)]
 	BlockNotFound(String),
+	/// Supplied parent_number is ahead of the best block
+	#[display(fmt = "Supplied parent_number: {} is ahead of the best block", _0)]
+	#[from(ignore)]
+	BlockNumberNotFound(u64),
+	/// A block has been sealed too recently, see `ManualSealConfig::min_seal_interval`
+	#[display(fmt = "Block sealed too soon after the previous one, retry in {:?}", _0)]
+	#[from(ignore)]
+	TooSoon(Duration),
+	/// The block would include more transactions than allowed, see `max_extrinsics`
+	#[display(fmt = "Block would include {} transactions, more than the maximum of {}", _0, _1)]
+	#[from(ignore)]
+	TooManyExtrinsics(usize, usize),
+	/// The seal predicate refused sealing on top of the given parent, see
+	/// `ManualSealConfig::seal_predicate`
+	#[display(fmt = "Sealing on top of block {} refused by the seal predicate", _0)]
+	#[from(ignore)]
+	SealRefused(String),
+	/// The runtime rejected the inherents of the proposed block, see
+	/// `ManualSealConfig::validate_inherents`
+	#[display(fmt = "Invalid inherents: {}", _0)]
+	#[from(ignore)]
+	InvalidInherents(String),
+	/// The transaction wasn't included in any of the blocks sealed for it,
+	/// see `EngineCommand::SealUntilIncluded`
+	#[display(fmt = "Transaction {} not included within {} blocks", _0, _1)]
+	#[from(ignore)]
+	TransactionNotIncluded(String, u32),
 	/// Some string error
 	#[display(fmt = "{}", _0)]
 	#[from(ignore)]
@@ -78,11 +111,16 @@ impl Error {
 		use Error::*;
 		match self {
 			BlockImportError(_) => codes::BLOCK_IMPORT_FAILED,
-			BlockNotFound(_) => codes::BLOCK_NOT_FOUND,
+			BlockNotFound(_) | BlockNumberNotFound(_) => codes::BLOCK_NOT_FOUND,
 			EmptyTransactionPool => codes::EMPTY_TRANSACTION_POOL,
 			ConsensusError(_) => codes::CONSENSUS_ERROR,
 			InherentError(_) => codes::INHERENTS_ERROR,
 			BlockchainError(_) => codes::BLOCKCHAIN_ERROR,
+			TooSoon(_) => codes::TOO_SOON,
+			TooManyExtrinsics(..) => codes::TOO_MANY_EXTRINSICS,
+			SealRefused(_) => codes::SEAL_REFUSED,
+			InvalidInherents(_) => codes::INVALID_INHERENTS,
+			TransactionNotIncluded(..) => codes::TRANSACTION_NOT_INCLUDED,
 			SendError(_) | Canceled(_) => codes::SERVER_SHUTTING_DOWN,
 			_ => codes::UNKNOWN_ERROR
 		}
@@ -16,15 +16,17 @@
 
 //! Block finalization utilities
 
-use crate::rpc;
+use crate::{Error, rpc};
 use sp_runtime::{
 	Justification,
-	traits::Block as BlockT,
+	traits::{Block as BlockT, NumberFor, One},
 	generic::BlockId,
 };
 use std::sync::Arc;
 use sc_client_api::backend::{Backend as ClientBackend, Finalizer};
+use sp_blockchain::HeaderBackend;
 use std::marker::PhantomData;
+use futures::channel::oneshot;
 
 /// params for block finalization.
 pub struct FinalizeBlockParams<B: BlockT, F, CB> {
@@ -36,13 +38,17 @@ pub struct FinalizeBlockParams<B: BlockT, F, CB> {
 	pub justification: Option<Justification>,
 	/// Finalizer trait object.
 	pub finalizer: Arc<F>,
+	/// decides whether a finalization error is fatal
+	pub is_fatal: fn(&Error) -> bool,
 	/// phantom type to pin the Backend type
 	pub _phantom: PhantomData<CB>,
 }
 
 
 /// finalizes a block in the backend with the given params.
-pub async fn finalize_block<B, F, CB>(params: FinalizeBlockParams<B, F, CB>)
+///
+/// fails only if finalization failed with a fatal error, which isn't sent to the rpc.
+pub async fn finalize_block<B, F, CB>(params: FinalizeBlockParams<B, F, CB>) -> Result<(), Error>
 	where
 		B: BlockT,
 		F: Finalizer<B, CB>,
@@ -53,17 +59,136 @@ pub async fn finalize_block<B, F, CB>(params: FinalizeBlockParams<B, F, CB>)
 		mut sender,
 		justification,
 		finalizer,
+		is_fatal,
 		..
 	} = params;
 
 	match finalizer.finalize_block(BlockId::Hash(hash), justification, true) {
 		Err(e) => {
 			log::warn!("Failed to finalize block {:?}", e);
-			rpc::send_result(&mut sender, Err(e.into()))
+			let e: Error = e.into();
+			if is_fatal(&e) {
+				return Err(e)
+			}
+			rpc::send_result(&mut sender, Err(e))
 		}
 		Ok(()) => {
 			log::info!("✅ Successfully finalized block: {}", hash);
 			rpc::send_result(&mut sender, Ok(()))
 		}
 	}
+
+	Ok(())
+}
+
+/// params for finalizing a range of blocks.
+pub struct FinalizeRangeParams<B: BlockT, F, CB> {
+	/// hash of the first block of the range
+	pub from: <B as BlockT>::Hash,
+	/// hash of the last block of the range
+	pub to: <B as BlockT>::Hash,
+	/// sender to report errors/success to the rpc.
+	pub sender: rpc::Sender<()>,
+	/// finalization justification of the last block of the range
+	pub justification: Option<Justification>,
+	/// Finalizer trait object.
+	pub finalizer: Arc<F>,
+	/// decides whether a finalization error is fatal
+	pub is_fatal: fn(&Error) -> bool,
+	/// phantom type to pin the Backend type
+	pub _phantom: PhantomData<CB>,
+}
+
+/// finalizes the blocks of the best chain from `from` to `to`, in order, with `finalize_block`.
+///
+/// blocks that are already finalized are skipped, and finalization stops at the first failure.
+/// fails only with a fatal error, which isn't sent to the rpc.
+pub async fn finalize_range<B, F, CB>(params: FinalizeRangeParams<B, F, CB>) -> Result<(), Error>
+	where
+		B: BlockT,
+		F: Finalizer<B, CB> + HeaderBackend<B>,
+		CB: ClientBackend<B>,
+{
+	let FinalizeRangeParams {
+		from,
+		to,
+		mut sender,
+		mut justification,
+		finalizer,
+		is_fatal,
+		..
+	} = params;
+
+	let hashes = match canonical_range(&*finalizer, from, to) {
+		Ok(hashes) => hashes,
+		Err(e) if is_fatal(&e) => return Err(e),
+		Err(e) => {
+			rpc::send_result(&mut sender, Err(e));
+			return Ok(())
+		},
+	};
+
+	for hash in hashes {
+		let (tx, rx) = oneshot::channel();
+		finalize_block(FinalizeBlockParams {
+			hash,
+			sender: Some(tx),
+			justification: if hash == to { justification.take() } else { None },
+			finalizer: finalizer.clone(),
+			is_fatal,
+			_phantom: PhantomData,
+		}).await?;
+
+		match rx.await {
+			Ok(Ok(())) => {},
+			Ok(Err(e)) => {
+				rpc::send_result(&mut sender, Err(e));
+				return Ok(())
+			},
+			Err(e) => {
+				rpc::send_result(&mut sender, Err(e.into()));
+				return Ok(())
+			},
+		}
+	}
+
+	rpc::send_result(&mut sender, Ok(()));
+	Ok(())
+}
+
+/// returns the hashes of the blocks from `from` to `to` that aren't finalized yet, checking that
+/// both blocks are on the best chain and that `from` is an ancestor of `to`.
+fn canonical_range<B, C>(
+	client: &C,
+	from: <B as BlockT>::Hash,
+	to: <B as BlockT>::Hash,
+) -> Result<Vec<<B as BlockT>::Hash>, Error>
+	where
+		B: BlockT,
+		C: HeaderBackend<B>,
+{
+	let canonical_number = |hash: <B as BlockT>::Hash| -> Result<NumberFor<B>, Error> {
+		let number = client.number(hash)?
+			.ok_or_else(|| Error::BlockNotFound(format!("{}", hash)))?;
+		if client.hash(number)? != Some(hash) {
+			return Err(Error::StringError(format!("Block {} isn't on the best chain", hash)))
+		}
+		Ok(number)
+	};
+	let from_number = canonical_number(from)?;
+	let to_number = canonical_number(to)?;
+	if from_number > to_number {
+		return Err(Error::StringError(format!("Block {} isn't an ancestor of {}", from, to)))
+	}
+
+	let mut number = std::cmp::max(from_number, client.info().finalized_number + One::one());
+	let mut hashes = Vec::new();
+	while number <= to_number {
+		let hash = client.hash(number)?
+			.ok_or_else(|| Error::BlockNotFound(format!("{}", number)))?;
+		hashes.push(hash);
+		number = number + One::one();
+	}
+
+	Ok(hashes)
 }
@@ -0,0 +1,93 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Import of blocks built outside of the engine
+
+use crate::{Error, ManualSealConfig, rpc, seal_new_block::fork_choice};
+use codec::Decode;
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
+use sp_consensus::{
+	BlockImport, BlockImportParams, BlockOrigin, ImportResult, import_queue::BoxBlockImport,
+};
+use sp_blockchain::HeaderBackend;
+use std::{collections::HashMap, sync::Arc};
+
+/// params for importing a block built outside of the engine.
+pub struct ImportBlockParams<'a, B: BlockT, HB, T> {
+	/// SCALE-encoded header of the block
+	pub header: Vec<u8>,
+	/// SCALE-encoded extrinsics of the block
+	pub body: Vec<Vec<u8>>,
+	/// instantly finalize this block?
+	pub finalize: bool,
+	/// sender to report the result of the import to the rpc.
+	pub sender: rpc::Sender<ImportResult>,
+	/// header backend
+	pub client: Arc<HB>,
+	/// block import object
+	pub block_import: &'a mut BoxBlockImport<B, T>,
+	/// configuration of the authorship task
+	pub config: &'a ManualSealConfig<<B as BlockT>::Header>,
+}
+
+/// decodes and imports a block with the given params.
+///
+/// the header is imported as is, digest included, and the block is executed by the block import.
+/// fails only if the import failed with an error that `ManualSealConfig::is_fatal` deems fatal,
+/// in which case the error isn't sent to the rpc.
+pub async fn import_block<B, HB, T>(
+	ImportBlockParams {
+		header,
+		body,
+		finalize,
+		mut sender,
+		client,
+		block_import,
+		config,
+	}: ImportBlockParams<'_, B, HB, T>
+) -> Result<(), Error>
+	where
+		B: BlockT,
+		HB: HeaderBackend<B>,
+{
+	let mut import = || {
+		let header = <B as BlockT>::Header::decode(&mut &header[..])
+			.map_err(|err| Error::StringError(format!("invalid block header: {}", err)))?;
+		let body = body.iter()
+			.map(|xt| <B as BlockT>::Extrinsic::decode(&mut &xt[..]))
+			.collect::<Result<Vec<_>, _>>()
+			.map_err(|err| Error::StringError(format!("invalid block extrinsic: {}", err)))?;
+
+		let mut params = BlockImportParams::new(BlockOrigin::File, header.clone());
+		params.body = Some(body);
+		params.finalized = finalize;
+		params.fork_choice = Some(fork_choice::<B, _>(&*client, config, None, &header)?);
+
+		let result = block_import.import_block(params, HashMap::new())?;
+		if let ImportResult::Imported(_) = result {
+			log::info!("✅ Successfully imported block: {}", header.hash());
+		}
+		Ok(result)
+	};
+
+	match import() {
+		Err(err) if (config.is_fatal)(&err) => Err(err),
+		result => {
+			rpc::send_result(&mut sender, result);
+			Ok(())
+		},
+	}
+}
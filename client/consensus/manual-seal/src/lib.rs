@@ -22,30 +22,189 @@
 use futures::prelude::*;
 use sp_consensus::{
 	Environment, Proposer, ForkChoiceStrategy, BlockImportParams, BlockOrigin, SelectChain,
-	import_queue::{BasicQueue, CacheKeyId, Verifier, BoxBlockImport},
+	Error as ConsensusError, import_queue::{BasicQueue, CacheKeyId, Verifier, BoxBlockImport},
 };
+use sp_api::ProvideRuntimeApi;
+use sp_block_builder::BlockBuilder as BlockBuilderApi;
 use sp_blockchain::HeaderBackend;
 use sp_inherents::InherentDataProviders;
-use sp_runtime::{traits::Block as BlockT, Justification};
+use sp_runtime::{
+	traits::{Block as BlockT, Header as HeaderT, NumberFor, UniqueSaturatedInto},
+	Justification,
+};
 use sc_client_api::backend::{Backend as ClientBackend, Finalizer};
 use sc_transaction_pool::txpool;
-use std::{sync::Arc, marker::PhantomData};
+use std::{
+	cmp::Ordering, convert::TryFrom, fmt, sync::Arc, marker::PhantomData,
+	time::{Duration, Instant, SystemTime},
+};
 use prometheus_endpoint::Registry;
 
 mod error;
 mod finalize_block;
+mod import_block;
 mod seal_new_block;
+pub mod record;
 pub mod rpc;
+#[cfg(any(test, feature = "test-helpers"))]
+pub mod test_helpers;
 
 use self::{
-	finalize_block::{finalize_block, FinalizeBlockParams},
+	finalize_block::{finalize_block, finalize_range, FinalizeBlockParams, FinalizeRangeParams},
+	import_block::{import_block, ImportBlockParams},
 	seal_new_block::{seal_new_block, SealBlockParams},
 };
 pub use self::{
 	error::Error,
-	rpc::{EngineCommand, CreatedBlock},
+	rpc::{
+		EngineCommand, CreatedBlock, ChainEntry, ChainHead, SealOutcome, SealParams,
+		TransactionSelection,
+	},
+	seal_new_block::{SealPhase, SealTimings},
 };
 
+/// Default number of times a block import failing with a transient error is retried.
+const DEFAULT_MAX_IMPORT_RETRIES: u32 = 3;
+
+/// Default delay between two attempts at importing a block.
+const DEFAULT_IMPORT_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Configuration of the manual seal authorship task, for blocks with headers of type `H`.
+#[derive(Clone, Debug)]
+pub struct ManualSealConfig<H> {
+	/// Number of times the import of a sealed block is retried if it fails with a transient
+	/// error, see `is_transient`. Blocks sealed with an overridden state root are never retried.
+	pub max_import_retries: u32,
+	/// Delay between two attempts at importing a sealed block.
+	pub import_retry_backoff: Duration,
+	/// Decides which import errors are transient, i.e. may go away if the import is tried again.
+	/// By default, only the state being unavailable and the import being interrupted are
+	/// transient: the client reports every other import failure, including invalid blocks, as
+	/// `ClientImport`, so that one is only worth retrying with a block import known to fail
+	/// temporarily with it.
+	pub is_transient: fn(&ConsensusError) -> bool,
+	/// Minimum delay between two sealed blocks. `SealNewBlock` commands arriving sooner than
+	/// that after the previous one are rejected with `Error::TooSoon`, whatever their source.
+	pub min_seal_interval: Option<Duration>,
+	/// If true, sealed blocks don't become the best block unless `set_best` is passed with the
+	/// `SealNewBlock` command, so that competing forks don't change the best block behind the
+	/// caller's back. As blocks are built on top of the best block by default, commands should
+	/// then specify their parent explicitly.
+	pub manual_best: bool,
+	/// If true, the inherents of every proposed block are checked by the runtime before the
+	/// block is imported. A block failing the check isn't imported, and the command fails with
+	/// `Error::InvalidInherents` describing the failed checks.
+	pub validate_inherents: bool,
+	/// If set, decides whether sealed blocks become the best block, unless `set_best` is passed
+	/// with the `SealNewBlock` command. The header of the sealed block is compared to the header
+	/// of the current best block, and the sealed block becomes the best block if it compares
+	/// greater. Takes precedence over `manual_best` and the longest chain rule.
+	pub fork_choice: Option<fn(&H, &H) -> Ordering>,
+	/// If set, consulted with the header of the parent before sealing a block. If it returns
+	/// false, the `SealNewBlock` command fails with `Error::SealRefused` and no block is sealed.
+	pub seal_predicate: Option<SealPredicate<H>>,
+	/// If set, blocks sealed without an explicit parent are built on a block drawn among the best
+	/// block and its latest ancestors, rather than always on the best block, e.g. to fuzz fork
+	/// choice. The draws only depend on the seed, so that the same commands produce the same
+	/// forks.
+	pub random_fork: Option<RandomFork>,
+	/// If set, the mock clock starts frozen at this time, in milliseconds since the UNIX epoch,
+	/// instead of following the wall clock until the first `AdvanceTime` or `SetTime` command.
+	/// Blocks built with the inherent data providers then always get their timestamp from the mock
+	/// clock, which only moves on these commands, so that the same commands produce the same
+	/// blocks.
+	pub frozen_clock: Option<u64>,
+	/// Decides which errors are fatal. A command failing with a fatal error isn't answered and
+	/// the authorship task terminates with that error, so that it can be restarted by a
+	/// supervisor. By default, no error is fatal and every error is reported to the command's
+	/// sender.
+	pub is_fatal: fn(&Error) -> bool,
+	/// If set, the time spent in each phase of sealing a block is sent to this channel for
+	/// every block sealed. Nothing is measured otherwise.
+	pub timings: Option<futures::channel::mpsc::UnboundedSender<SealTimings>>,
+}
+
+impl<H> Default for ManualSealConfig<H> {
+	fn default() -> Self {
+		ManualSealConfig {
+			max_import_retries: DEFAULT_MAX_IMPORT_RETRIES,
+			import_retry_backoff: DEFAULT_IMPORT_RETRY_BACKOFF,
+			is_transient: seal_new_block::is_transient,
+			min_seal_interval: None,
+			manual_best: false,
+			validate_inherents: false,
+			fork_choice: None,
+			seal_predicate: None,
+			random_fork: None,
+			frozen_clock: None,
+			is_fatal: |_| false,
+			timings: None,
+		}
+	}
+}
+
+/// Predicate deciding whether a block can be sealed on top of a parent, see
+/// `ManualSealConfig::seal_predicate`.
+///
+/// It is typically a closure holding the client, e.g. to query a runtime API at the parent.
+pub struct SealPredicate<H>(Arc<dyn Fn(&H) -> bool + Send + Sync>);
+
+impl<H> SealPredicate<H> {
+	/// Creates a predicate that is given the header of the parent of the block to seal.
+	pub fn new(predicate: impl Fn(&H) -> bool + Send + Sync + 'static) -> Self {
+		SealPredicate(Arc::new(predicate))
+	}
+
+	/// Returns true if a block can be sealed on top of `parent`.
+	pub fn allows(&self, parent: &H) -> bool {
+		(self.0)(parent)
+	}
+}
+
+impl<H> Clone for SealPredicate<H> {
+	fn clone(&self) -> Self {
+		SealPredicate(self.0.clone())
+	}
+}
+
+impl<H> fmt::Debug for SealPredicate<H> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_tuple("SealPredicate").finish()
+	}
+}
+
+/// Random choice of the parents of sealed blocks, see `ManualSealConfig::random_fork`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RandomFork {
+	/// Seed of the draws.
+	pub seed: u64,
+	/// Maximum number of blocks between the best block and the parent of a sealed block. Each
+	/// depth from `0`, building on the best block, to `max_depth` is equally likely.
+	pub max_depth: u32,
+}
+
+/// deterministic generator of the depths of random forks, using splitmix64.
+struct ForkRng {
+	state: u64,
+	max_depth: u32,
+}
+
+impl ForkRng {
+	fn new(random_fork: RandomFork) -> Self {
+		ForkRng { state: random_fork.seed, max_depth: random_fork.max_depth }
+	}
+
+	/// draws the number of blocks between the best block and the parent of the next block.
+	fn next_depth(&mut self) -> u32 {
+		self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+		let mut z = self.state;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+		z ^= z >> 31;
+		(z % (u64::from(self.max_depth) + 1)) as u32
+	}
+}
+
 /// The verifier for the manual seal engine; instantly finalizes.
 struct ManualSealVerifier;
 
@@ -88,6 +247,9 @@ pub fn import_queue<Block, Transaction>(
 }
 
 /// Creates the background authorship task for the manual seal engine.
+///
+/// The task runs until the stream of commands ends, or until a command fails with an error
+/// deemed fatal by `ManualSealConfig::is_fatal`.
 pub async fn run_manual_seal<B, CB, E, C, A, SC, S, T>(
 	mut block_import: BoxBlockImport<B, T>,
 	mut env: E,
@@ -96,40 +258,87 @@ pub async fn run_manual_seal<B, CB, E, C, A, SC, S, T>(
 	mut commands_stream: S,
 	select_chain: SC,
 	inherent_data_providers: InherentDataProviders,
-)
+	config: ManualSealConfig<<B as BlockT>::Header>,
+) -> Result<(), Error>
 	where
 		A: txpool::ChainApi<Block=B> + 'static,
 		B: BlockT + 'static,
-		C: HeaderBackend<B> + Finalizer<B, CB> + 'static,
+		C: HeaderBackend<B> + Finalizer<B, CB> + ProvideRuntimeApi<B> + 'static,
+		C::Api: BlockBuilderApi<B, Error = sp_blockchain::Error>,
 		CB: ClientBackend<B> + 'static,
 		E: Environment<B> + 'static,
+		E::Proposer: Proposer<B, Transaction = T>,
 		E::Error: std::fmt::Display,
 		<E::Proposer as Proposer<B>>::Error: std::fmt::Display,
 		S: Stream<Item=EngineCommand<<B as BlockT>::Hash>> + Unpin + 'static,
 		SC: SelectChain<B> + 'static,
 {
+	let mut last_seal: Option<Instant> = None;
+	// time of the mock clock, in milliseconds, once set.
+	let mut mock_time: Option<u64> = config.frozen_clock;
+	let mut fork_rng = config.random_fork.map(ForkRng::new);
+	// number of seal commands left to fail, and the error they fail with.
+	#[cfg(any(test, feature = "test-helpers"))]
+	let mut injected_failure: Option<(u32, fn() -> Error)> = None;
 	while let Some(command) = commands_stream.next().await {
 		match command {
-			EngineCommand::SealNewBlock {
-				create_empty,
-				finalize,
-				parent_hash,
-				sender,
-			} => {
-				seal_new_block(
+			EngineCommand::SealNewBlock { mut params, mut sender } => {
+				#[cfg(any(test, feature = "test-helpers"))]
+				if fail_injected(&mut injected_failure, &mut sender, config.is_fatal)? {
+					continue
+				}
+
+				if let Err(err) = check_seal_interval(config.min_seal_interval, last_seal) {
+					rpc::send_result(&mut sender, Err(err));
+					continue
+				}
+
+				#[cfg(any(test, feature = "test-helpers"))]
+				let skip_inherents = params.skip_inherents;
+				#[cfg(not(any(test, feature = "test-helpers")))]
+				let skip_inherents = false;
+				// the mock clock only applies to blocks built with the inherent data providers.
+				if params.timestamp.is_none() && !skip_inherents && params.inherent_data.is_none() {
+					params.timestamp = mock_time;
+				}
+
+				// only blocks built on the best block by default are moved to a random fork.
+				let fork_depth = match (&mut fork_rng, &params.parent_hash, params.parent_number) {
+					(Some(rng), None, None) => Some(rng.next_depth()),
+					_ => None,
+				};
+
+				let sealed = seal_new_block(
 					SealBlockParams {
 						sender,
-						parent_hash,
-						finalize,
-						create_empty,
+						params,
+						fork_depth,
 						env: &mut env,
 						select_chain: &select_chain,
 						block_import: &mut block_import,
 						inherent_data_provider: &inherent_data_providers,
 						pool: pool.clone(),
 						client: client.clone(),
+						config: &config,
+					}
+				).await?;
+				// failed seals don't delay the next one.
+				if sealed {
+					last_seal = Some(Instant::now());
+				}
+			}
+			EngineCommand::ImportBlock { header, body, finalize, sender } => {
+				import_block(
+					ImportBlockParams {
+						header,
+						body,
+						finalize,
+						sender,
+						client: client.clone(),
+						block_import: &mut block_import,
+						config: &config,
 					}
-				).await;
+				).await?
 			}
 			EngineCommand::FinalizeBlock { hash, sender, justification } => {
 				finalize_block(
@@ -138,12 +347,216 @@ pub async fn run_manual_seal<B, CB, E, C, A, SC, S, T>(
 						sender,
 						justification,
 						finalizer: client.clone(),
+						is_fatal: config.is_fatal,
+						_phantom: PhantomData,
+					}
+				).await?
+			}
+			EngineCommand::FinalizeRange { from, to, sender, justification } => {
+				finalize_range(
+					FinalizeRangeParams {
+						from,
+						to,
+						sender,
+						justification,
+						finalizer: client.clone(),
+						is_fatal: config.is_fatal,
 						_phantom: PhantomData,
 					}
-				).await
+				).await?
+			}
+			EngineCommand::QueryHead { mut sender } => {
+				rpc::send_result(&mut sender, query_head(&select_chain, &*client));
+			}
+			EngineCommand::ExportChain { from, to, mut sender } => {
+				rpc::send_result(&mut sender, export_chain(&*client, from, to));
+			}
+			EngineCommand::AdvanceTime { by, mut sender } => {
+				let now = mock_time.unwrap_or_else(system_time);
+				let time = now.saturating_add(by.as_millis().unique_saturated_into());
+				mock_time = Some(time);
+				rpc::send_result(&mut sender, Ok(time));
+			}
+			EngineCommand::SetTime { to, mut sender } => {
+				mock_time = Some(to);
+				rpc::send_result(&mut sender, Ok(to));
+			}
+			#[cfg(any(test, feature = "test-helpers"))]
+			EngineCommand::InjectFailure { count, error, mut sender } => {
+				injected_failure = if count > 0 { Some((count, error)) } else { None };
+				rpc::send_result(&mut sender, Ok(()));
+			}
+			EngineCommand::SealUntilIncluded { tx_hash, max_blocks, mut sender } => {
+				#[cfg(any(test, feature = "test-helpers"))]
+				if fail_injected(&mut injected_failure, &mut sender, config.is_fatal)? {
+					continue
+				}
+
+				// the whole command counts as a single seal for the minimum interval.
+				if let Err(err) = check_seal_interval(config.min_seal_interval, last_seal) {
+					rpc::send_result(&mut sender, Err(err));
+					continue
+				}
+
+				let mut result = Err(Error::TransactionNotIncluded(format!("{}", tx_hash), max_blocks));
+				for _ in 0..max_blocks {
+					let (block_sender, block_receiver) = futures::channel::oneshot::channel();
+					let sealed = seal_new_block(
+						SealBlockParams {
+							sender: Some(block_sender),
+							params: SealParams { timestamp: mock_time, ..Default::default() },
+							fork_depth: None,
+							env: &mut env,
+							select_chain: &select_chain,
+							block_import: &mut block_import,
+							inherent_data_provider: &inherent_data_providers,
+							pool: pool.clone(),
+							client: client.clone(),
+							config: &config,
+						}
+					).await?;
+					if sealed {
+						last_seal = Some(Instant::now());
+					}
+
+					// stops at the first error, e.g. once the pool is empty.
+					match block_receiver.await {
+						Ok(Ok(SealOutcome::Sealed(block))) if block.transactions.contains(&tx_hash) => {
+							result = Ok(block.hash);
+							break
+						}
+						Ok(Ok(_)) => {}
+						Ok(Err(err)) => {
+							result = Err(err);
+							break
+						}
+						Err(err) => {
+							result = Err(err.into());
+							break
+						}
+					}
+				}
+				rpc::send_result(&mut sender, result);
 			}
 		}
 	}
+
+	Ok(())
+}
+
+/// fails with `Error::TooSoon` if less than `min_seal_interval` has elapsed since `last_seal`.
+fn check_seal_interval(
+	min_seal_interval: Option<Duration>,
+	last_seal: Option<Instant>,
+) -> Result<(), Error> {
+	if let (Some(min_interval), Some(last_seal)) = (min_seal_interval, last_seal) {
+		let elapsed = last_seal.elapsed();
+		if elapsed < min_interval {
+			return Err(Error::TooSoon(min_interval - elapsed))
+		}
+	}
+
+	Ok(())
+}
+
+/// fails a seal command with the next injected failure, if any, see
+/// `EngineCommand::InjectFailure`.
+///
+/// returns true if the command has failed, or the error if `is_fatal` deems it fatal.
+#[cfg(any(test, feature = "test-helpers"))]
+fn fail_injected<T: fmt::Debug>(
+	injected_failure: &mut Option<(u32, fn() -> Error)>,
+	sender: &mut rpc::Sender<T>,
+	is_fatal: fn(&Error) -> bool,
+) -> Result<bool, Error> {
+	let (count, error) = match injected_failure.take() {
+		Some(injected) => injected,
+		None => return Ok(false),
+	};
+	if count > 1 {
+		*injected_failure = Some((count - 1, error));
+	}
+
+	let error = error();
+	if is_fatal(&error) {
+		return Err(error)
+	}
+	rpc::send_result(sender, Err(error));
+	Ok(true)
+}
+
+/// returns the current system time, in milliseconds.
+fn system_time() -> u64 {
+	SystemTime::now()
+		.duration_since(SystemTime::UNIX_EPOCH)
+		.map(|duration| duration.as_millis().unique_saturated_into())
+		.unwrap_or(0)
+}
+
+/// reads the best block from the select chain and the finalized block from the client.
+fn query_head<B, C, SC>(select_chain: &SC, client: &C) -> Result<ChainHead<<B as BlockT>::Hash>, Error>
+	where
+		B: BlockT,
+		C: HeaderBackend<B>,
+		SC: SelectChain<B>,
+{
+	let best = select_chain.best_chain()?;
+	let info = client.info();
+
+	Ok(ChainHead {
+		best_hash: best.hash(),
+		best_number: (*best.number()).unique_saturated_into(),
+		finalized_hash: info.finalized_hash,
+		finalized_number: info.finalized_number.unique_saturated_into(),
+	})
+}
+
+/// reads the blocks of the best chain from number `from` to number `to` from the client.
+fn export_chain<B, C>(
+	client: &C,
+	from: u64,
+	to: u64,
+) -> Result<Vec<ChainEntry<<B as BlockT>::Hash>>, Error>
+	where
+		B: BlockT,
+		C: HeaderBackend<B>,
+{
+	if from > to {
+		return Err(Error::StringError(format!("Invalid range of blocks: {} to {}", from, to)))
+	}
+
+	let finalized_number: u64 = client.info().finalized_number.unique_saturated_into();
+	(from..=to).map(|number| {
+		let hash = match NumberFor::<B>::try_from(number) {
+			Ok(n) => client.hash(n)?,
+			Err(_) => None,
+		};
+		match hash {
+			Some(hash) => Ok(ChainEntry { hash, number, finalized: number <= finalized_number }),
+			None => Err(Error::BlockNumberNotFound(number)),
+		}
+	}).collect()
+}
+
+/// creates a stream of commands sealing a new block, without creating empty blocks, for every
+/// transaction imported into the transaction pool.
+///
+/// this is the stream of commands instant seal runs on. the commands have no sender, so their
+/// results are only logged.
+pub fn pool_command_stream<A>(
+	pool: &txpool::Pool<A>,
+) -> impl Stream<Item=EngineCommand<<<A as txpool::ChainApi>::Block as BlockT>::Hash>>
+	where
+		A: txpool::ChainApi + 'static,
+{
+	pool.validated_pool()
+		.import_notification_stream()
+		.map(|_| {
+			EngineCommand::SealNewBlock {
+				params: SealParams::default(),
+				sender: None,
+			}
+		})
 }
 
 /// runs the background authorship task for the instant seal engine.
@@ -156,29 +569,23 @@ pub async fn run_instant_seal<B, CB, E, C, A, SC, T>(
 	pool: Arc<txpool::Pool<A>>,
 	select_chain: SC,
 	inherent_data_providers: InherentDataProviders,
-)
+	config: ManualSealConfig<<B as BlockT>::Header>,
+) -> Result<(), Error>
 	where
 		A: txpool::ChainApi<Block=B> + 'static,
 		B: BlockT + 'static,
-		C: HeaderBackend<B> + Finalizer<B, CB> + 'static,
+		C: HeaderBackend<B> + Finalizer<B, CB> + ProvideRuntimeApi<B> + 'static,
+		C::Api: BlockBuilderApi<B, Error = sp_blockchain::Error>,
 		CB: ClientBackend<B> + 'static,
 		E: Environment<B> + 'static,
+		E::Proposer: Proposer<B, Transaction = T>,
 		E::Error: std::fmt::Display,
 		<E::Proposer as Proposer<B>>::Error: std::fmt::Display,
 		SC: SelectChain<B> + 'static
 {
 	// instant-seal creates blocks as soon as transactions are imported
 	// into the transaction pool.
-	let commands_stream = pool.validated_pool()
-		.import_notification_stream()
-		.map(|_| {
-			EngineCommand::SealNewBlock {
-				create_empty: false,
-				finalize: false,
-				parent_hash: None,
-				sender: None,
-			}
-		});
+	let commands_stream = pool_command_stream(&pool);
 
 	run_manual_seal(
 		block_import,
@@ -188,6 +595,7 @@ pub async fn run_instant_seal<B, CB, E, C, A, SC, T>(
 		commands_stream,
 		select_chain,
 		inherent_data_providers,
+		config,
 	).await
 }
 
@@ -199,20 +607,197 @@ mod tests {
 		TestClientBuilderExt,
 		AccountKeyring::*,
 		TestClientBuilder,
+		Backend,
+		TestClient,
+		runtime::{Block, Extrinsic, Header},
 	};
 	use sc_transaction_pool::{BasicPool, RevalidationType, txpool::Options};
 	use substrate_test_runtime_transaction_pool::{TestApi, uxt};
 	use sp_transaction_pool::{TransactionPool, MaintainedTransactionPool, TransactionSource};
 	use sp_runtime::generic::BlockId;
-	use sp_consensus::ImportedAux;
+	use sp_consensus::{
+		ImportedAux, BlockImport, BlockCheckParams, ImportResult, Error as ConsensusError,
+	};
 	use sp_inherents::InherentDataProviders;
 	use sc_basic_authorship::ProposerFactory;
 	use sc_client_api::BlockBackend;
+	use codec::Encode;
+	use std::{collections::HashMap, sync::atomic::{AtomicUsize, Ordering}};
+	use crate::test_helpers::{start_test_engine, TestChain, TestEngine};
 
 	fn api() -> Arc<TestApi> {
 		Arc::new(TestApi::empty())
 	}
 
+	/// Block import that fails with `error` a given number of times before delegating to the
+	/// inner block import.
+	struct FlakyBlockImport<I> {
+		inner: I,
+		failures: Arc<AtomicUsize>,
+		error: fn() -> ConsensusError,
+	}
+
+	impl<B: BlockT, I: BlockImport<B, Error = ConsensusError>> BlockImport<B> for FlakyBlockImport<I> {
+		type Error = ConsensusError;
+		type Transaction = I::Transaction;
+
+		fn check_block(&mut self, block: BlockCheckParams<B>) -> Result<ImportResult, Self::Error> {
+			self.inner.check_block(block)
+		}
+
+		fn import_block(
+			&mut self,
+			block: BlockImportParams<B, Self::Transaction>,
+			cache: HashMap<CacheKeyId, Vec<u8>>,
+		) -> Result<ImportResult, Self::Error> {
+			if self.failures.load(Ordering::SeqCst) > 0 {
+				self.failures.fetch_sub(1, Ordering::SeqCst);
+				return Err((self.error)())
+			}
+			self.inner.import_block(block, cache)
+		}
+	}
+
+	/// client whose runtime checks the timestamp inherent like a timestamp module would: it must
+	/// be provided, and mustn't be too far in the future. everything else is left to the inner
+	/// client.
+	struct TimestampChecking {
+		inner: Arc<TestClient>,
+	}
+
+	/// runtime api of `TimestampChecking`.
+	struct TimestampCheckingApi;
+
+	/// how far ahead of the wall clock the timestamp inherent may be, in milliseconds.
+	const MAX_TIMESTAMP_DRIFT: u64 = 30_000;
+
+	sp_api::mock_impl_runtime_apis! {
+		impl sp_block_builder::BlockBuilder<Block> for TimestampCheckingApi {
+			type Error = sp_blockchain::Error;
+
+			// blocks are built by the proposer, on top of the inner client: only
+			// `check_inherents` is ever called, the other calls return inert values.
+			fn apply_extrinsic(_extrinsic: Extrinsic) -> sp_runtime::ApplyExtrinsicResult {
+				Ok(Ok(()))
+			}
+
+			fn finalize_block() -> Header {
+				Header::new(
+					Default::default(),
+					Default::default(),
+					Default::default(),
+					Default::default(),
+					Default::default(),
+				)
+			}
+
+			fn inherent_extrinsics(_data: sp_inherents::InherentData) -> Vec<Extrinsic> {
+				Vec::new()
+			}
+
+			fn check_inherents(
+				_block: Block,
+				data: sp_inherents::InherentData,
+			) -> sp_inherents::CheckInherentsResult {
+				let mut result = sp_inherents::CheckInherentsResult::new();
+				let error = match data.get_data::<u64>(&sp_timestamp::INHERENT_IDENTIFIER) {
+					Ok(Some(timestamp)) if timestamp <= system_time() + MAX_TIMESTAMP_DRIFT => {
+						return result
+					},
+					Ok(Some(_)) => "the timestamp is too far in the future",
+					_ => "the timestamp inherent is missing",
+				};
+				result.put_error(
+					sp_timestamp::INHERENT_IDENTIFIER,
+					&sp_timestamp::InherentError::Other(error.into()),
+				).unwrap();
+				result
+			}
+
+			fn random_seed() -> sp_core::H256 {
+				Default::default()
+			}
+		}
+	}
+
+	impl ProvideRuntimeApi<Block> for TimestampChecking {
+		type Api = TimestampCheckingApi;
+
+		fn runtime_api<'a>(&'a self) -> sp_api::ApiRef<'a, Self::Api> {
+			TimestampCheckingApi.into()
+		}
+	}
+
+	impl HeaderBackend<Block> for TimestampChecking {
+		fn header(&self, id: BlockId<Block>) -> sp_blockchain::Result<Option<Header>> {
+			self.inner.header(id)
+		}
+
+		fn info(&self) -> sp_blockchain::Info<Block> {
+			self.inner.info()
+		}
+
+		fn status(&self, id: BlockId<Block>) -> sp_blockchain::Result<sp_blockchain::BlockStatus> {
+			self.inner.status(id)
+		}
+
+		fn number(&self, hash: sp_core::H256) -> sp_blockchain::Result<Option<u64>> {
+			self.inner.number(hash)
+		}
+
+		fn hash(&self, number: u64) -> sp_blockchain::Result<Option<sp_core::H256>> {
+			self.inner.hash(number)
+		}
+	}
+
+	impl Finalizer<Block, Backend> for TimestampChecking {
+		fn apply_finality(
+			&self,
+			operation: &mut sc_client_api::backend::ClientImportOperation<Block, Backend>,
+			id: BlockId<Block>,
+			justification: Option<Justification>,
+			notify: bool,
+		) -> sp_blockchain::Result<()> {
+			self.inner.apply_finality(operation, id, justification, notify)
+		}
+
+		fn finalize_block(
+			&self,
+			id: BlockId<Block>,
+			justification: Option<Justification>,
+			notify: bool,
+		) -> sp_blockchain::Result<()> {
+			Finalizer::finalize_block(&*self.inner, id, justification, notify)
+		}
+	}
+
+	/// starts an engine validating the inherents of its blocks with `TimestampChecking`.
+	fn start_timestamp_checking_engine() -> TestEngine {
+		let TestChain { client, select_chain, pool, pool_api } = TestChain::new();
+		let inherent_data_providers = InherentDataProviders::new();
+		inherent_data_providers
+			.register_provider(sp_timestamp::InherentDataProvider)
+			.unwrap();
+		let env = ProposerFactory::new(client.clone(), pool.clone(), None);
+		let (sink, stream) = futures::channel::mpsc::channel(1024);
+		let future = run_manual_seal(
+			Box::new(client.clone()),
+			env,
+			Arc::new(TimestampChecking { inner: client.clone() }),
+			pool.pool().clone(),
+			stream,
+			select_chain,
+			inherent_data_providers,
+			ManualSealConfig { validate_inherents: true, ..Default::default() },
+		);
+		std::thread::spawn(|| {
+			let mut rt = tokio::runtime::Runtime::new().unwrap();
+			// spawn the background authorship task
+			rt.block_on(future).unwrap();
+		});
+		TestEngine { client, pool, pool_api, commands: sink }
+	}
+
 	const SOURCE: TransactionSource = TransactionSource::External;
 
 	#[tokio::test]
@@ -239,10 +824,8 @@ mod tests {
 				let mut_sender =  Arc::get_mut(&mut sender).unwrap();
 				let sender = std::mem::take(mut_sender);
 				EngineCommand::SealNewBlock {
-					create_empty: false,
-					finalize: true,
-					parent_hash: None,
-					sender
+					params: SealParams { finalize: true, ..Default::default() },
+					sender,
 				}
 			});
 		let future = run_manual_seal(
@@ -253,22 +836,25 @@ mod tests {
 			stream,
 			select_chain,
 			inherent_data_providers,
+			ManualSealConfig::default(),
 		);
 		std::thread::spawn(|| {
 			let mut rt = tokio::runtime::Runtime::new().unwrap();
 			// spawn the background authorship task
-			rt.block_on(future);
+			rt.block_on(future).unwrap();
 		});
 		// submit a transaction to pool.
 		let result = pool.submit_one(&BlockId::Number(0), SOURCE, uxt(Alice, 0)).await;
 		// assert that it was successfully imported
 		assert!(result.is_ok());
 		// assert that the background task returns ok
-		let created_block = receiver.await.unwrap().unwrap();
+		let created_block = receiver.await.unwrap().unwrap().created_block().unwrap();
 		assert_eq!(
 			created_block,
 			CreatedBlock {
 				hash: created_block.hash.clone(),
+				size: created_block.size,
+				selection: created_block.selection,
 				aux: ImportedAux {
 					header_only: false,
 					clear_justification_requests: false,
@@ -276,7 +862,9 @@ mod tests {
 					bad_justification: false,
 					needs_finality_proof: false,
 					is_new_best: true,
-				}
+				},
+				inherents: vec![],
+				transactions: vec![result.unwrap()],
 			}
 		);
 		// assert that there's a new block in the db.
@@ -308,11 +896,12 @@ mod tests {
 			stream,
 			select_chain,
 			inherent_data_providers,
+			ManualSealConfig::default(),
 		);
 		std::thread::spawn(|| {
 			let mut rt = tokio::runtime::Runtime::new().unwrap();
 			// spawn the background authorship task
-			rt.block_on(future);
+			rt.block_on(future).unwrap();
 		});
 		// submit a transaction to pool.
 		let result = pool.submit_one(&BlockId::Number(0), SOURCE, uxt(Alice, 0)).await;
@@ -320,18 +909,18 @@ mod tests {
 		assert!(result.is_ok());
 		let (tx, rx) = futures::channel::oneshot::channel();
 		sink.send(EngineCommand::SealNewBlock {
-			parent_hash: None,
+			params: SealParams::default(),
 			sender: Some(tx),
-			create_empty: false,
-			finalize: false,
 		}).await.unwrap();
-		let created_block = rx.await.unwrap().unwrap();
+		let created_block = rx.await.unwrap().unwrap().created_block().unwrap();
 
 		// assert that the background task returns ok
 		assert_eq!(
 			created_block,
 			CreatedBlock {
 				hash: created_block.hash.clone(),
+				size: created_block.size,
+				selection: created_block.selection,
 				aux: ImportedAux {
 					header_only: false,
 					clear_justification_requests: false,
@@ -339,7 +928,9 @@ mod tests {
 					bad_justification: false,
 					needs_finality_proof: false,
 					is_new_best: true,
-				}
+				},
+				inherents: vec![],
+				transactions: vec![result.unwrap()],
 			}
 		);
 		// assert that there's a new block in the db.
@@ -380,11 +971,12 @@ mod tests {
 			stream,
 			select_chain,
 			inherent_data_providers,
+			ManualSealConfig::default(),
 		);
 		std::thread::spawn(|| {
 			let mut rt = tokio::runtime::Runtime::new().unwrap();
 			// spawn the background authorship task
-			rt.block_on(future);
+			rt.block_on(future).unwrap();
 		});
 		// submit a transaction to pool.
 		let result = pool.submit_one(&BlockId::Number(0), SOURCE, uxt(Alice, 0)).await;
@@ -393,12 +985,10 @@ mod tests {
 
 		let (tx, rx) = futures::channel::oneshot::channel();
 		sink.send(EngineCommand::SealNewBlock {
-			parent_hash: None,
+			params: SealParams::default(),
 			sender: Some(tx),
-			create_empty: false,
-			finalize: false,
 		}).await.unwrap();
-		let created_block = rx.await.unwrap().unwrap();
+		let created_block = rx.await.unwrap().unwrap().created_block().unwrap();
 		pool_api.increment_nonce(Alice.into());
 
 		// assert that the background task returns ok
@@ -406,14 +996,18 @@ mod tests {
 			created_block,
 			CreatedBlock {
 				hash: created_block.hash.clone(),
+				size: created_block.size,
+				selection: created_block.selection,
 				aux: ImportedAux {
 					header_only: false,
 					clear_justification_requests: false,
 					needs_justification: false,
 					bad_justification: false,
 					needs_finality_proof: false,
-					is_new_best: true
-				}
+					is_new_best: true,
+				},
+				inherents: vec![],
+				transactions: vec![result.unwrap()],
 			}
 		);
 		let block = client.block(&BlockId::Number(1)).unwrap().unwrap().block;
@@ -428,10 +1022,8 @@ mod tests {
 
 		let (tx1, rx1) = futures::channel::oneshot::channel();
 		assert!(sink.send(EngineCommand::SealNewBlock {
-			parent_hash: Some(created_block.hash),
+			params: SealParams { parent_hash: Some(created_block.hash), ..Default::default() },
 			sender: Some(tx1),
-			create_empty: false,
-			finalize: false,
 		}).await.is_ok());
 		assert_matches::assert_matches!(
 			rx1.await.expect("should be no error receiving"),
@@ -444,13 +1036,1304 @@ mod tests {
 		assert!(pool.submit_one(&BlockId::Number(1), SOURCE, uxt(Alice, 2)).await.is_ok());
 		let (tx2, rx2) = futures::channel::oneshot::channel();
 		assert!(sink.send(EngineCommand::SealNewBlock {
-			parent_hash: Some(created_block.hash),
+			params: SealParams { parent_hash: Some(created_block.hash), ..Default::default() },
 			sender: Some(tx2),
-			create_empty: false,
-			finalize: false,
 		}).await.is_ok());
-		let imported = rx2.await.unwrap().unwrap();
+		let imported = rx2.await.unwrap().unwrap().created_block().unwrap();
 		// assert that fork block is in the db
 		assert!(client.header(&BlockId::Hash(imported.hash)).unwrap().is_some())
 	}
+
+	#[tokio::test]
+	async fn manual_seal_retries_transient_import_errors() {
+		let chain = TestChain::new();
+		// the first import attempt fails, the second one goes through.
+		let failures = Arc::new(AtomicUsize::new(1));
+		let block_import = FlakyBlockImport {
+			inner: chain.client.clone(),
+			failures: failures.clone(),
+			error: || ConsensusError::StateUnavailable("database is locked".into()),
+		};
+		let engine = chain.start_with_block_import(
+			Box::new(block_import),
+			InherentDataProviders::new(),
+			ManualSealConfig {
+				max_import_retries: 1,
+				import_retry_backoff: Duration::from_millis(10),
+				..Default::default()
+			},
+		);
+		let created_block = engine
+			.seal(SealParams { create_empty: true, ..Default::default() })
+			.await
+			.unwrap()
+			.created_block()
+			.unwrap();
+
+		assert_eq!(failures.load(Ordering::SeqCst), 0);
+		assert!(created_block.aux.is_new_best);
+		// assert that the block eventually made it into the db.
+		let header = engine.client.header(&BlockId::Number(1)).unwrap().unwrap();
+		assert_eq!(header.hash(), created_block.hash);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_does_not_retry_definitive_import_errors() {
+		let chain = TestChain::new();
+		let failures = Arc::new(AtomicUsize::new(2));
+		let block_import = FlakyBlockImport {
+			inner: chain.client.clone(),
+			failures: failures.clone(),
+			error: || ConsensusError::ClientImport("block is known bad".into()),
+		};
+		let engine = chain.start_with_block_import(
+			Box::new(block_import),
+			InherentDataProviders::new(),
+			ManualSealConfig { import_retry_backoff: Duration::from_millis(10), ..Default::default() },
+		);
+
+		assert_matches::assert_matches!(
+			engine.seal(SealParams { create_empty: true, ..Default::default() }).await,
+			Err(Error::ConsensusError(ConsensusError::ClientImport(_)))
+		);
+		// the import was only attempted once.
+		assert_eq!(failures.load(Ordering::SeqCst), 1);
+		assert_eq!(engine.client.info().best_number, 0);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_does_not_retry_blocks_with_overridden_state_root() {
+		let chain = TestChain::new();
+		let failures = Arc::new(AtomicUsize::new(1));
+		let block_import = FlakyBlockImport {
+			inner: chain.client.clone(),
+			failures: failures.clone(),
+			error: || ConsensusError::StateUnavailable("database is locked".into()),
+		};
+		let engine = chain.start_with_block_import(
+			Box::new(block_import),
+			InherentDataProviders::new(),
+			ManualSealConfig { import_retry_backoff: Duration::from_millis(10), ..Default::default() },
+		);
+
+		// the transient failure would be retried without the state changes of the proposal.
+		assert_matches::assert_matches!(
+			engine.seal(SealParams {
+				create_empty: true,
+				state_root: Some(sp_core::H256::repeat_byte(0x42)),
+				..Default::default()
+			}).await,
+			Err(Error::ConsensusError(ConsensusError::StateUnavailable(_)))
+		);
+		assert_eq!(failures.load(Ordering::SeqCst), 0);
+		assert_eq!(engine.client.info().best_number, 0);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_reports_included_transactions() {
+		let engine = start_test_engine(ManualSealConfig::default());
+		let TestEngine { pool, .. } = &engine;
+		// submit two transactions to the pool.
+		let alice = pool.submit_one(&BlockId::Number(0), SOURCE, uxt(Alice, 0)).await.unwrap();
+		let bob = pool.submit_one(&BlockId::Number(0), SOURCE, uxt(Bob, 0)).await.unwrap();
+
+		let outcome = engine.seal(SealParams::default()).await.unwrap();
+		let created_block = outcome.created_block().unwrap();
+
+		// the test runtime doesn't have any inherents.
+		assert!(created_block.inherents.is_empty());
+		assert_eq!(created_block.transactions.len(), 2);
+		assert!(created_block.transactions.contains(&alice));
+		assert!(created_block.transactions.contains(&bob));
+	}
+
+	#[tokio::test]
+	async fn manual_seal_rejects_too_many_extrinsics() {
+		let engine = start_test_engine(ManualSealConfig::default());
+		let TestEngine { client, pool, .. } = &engine;
+		for nonce in 0..3 {
+			let result = pool.submit_one(&BlockId::Number(0), SOURCE, uxt(Alice, nonce)).await;
+			assert!(result.is_ok());
+		}
+
+		let seal_block = |max_extrinsics: Option<usize>| {
+			engine.seal(SealParams { max_extrinsics, ..Default::default() })
+		};
+
+		// the three transactions don't fit, and nothing is imported.
+		assert_matches::assert_matches!(
+			seal_block(Some(2)).await,
+			Err(Error::TooManyExtrinsics(3, 2))
+		);
+		assert!(client.header(&BlockId::Number(1)).unwrap().is_none());
+
+		let created_block = seal_block(Some(3)).await.unwrap().created_block().unwrap();
+		assert_eq!(created_block.transactions.len(), 3);
+		let header = client.header(&BlockId::Number(1)).unwrap().unwrap();
+		assert_eq!(header.hash(), created_block.hash);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_reports_phase_timings() {
+		let (timings_tx, mut timings_rx) = futures::channel::mpsc::unbounded();
+		let engine = start_test_engine(
+			ManualSealConfig { timings: Some(timings_tx), ..Default::default() },
+		);
+
+		let start = Instant::now();
+		engine.seal(SealParams { create_empty: true, ..Default::default() }).await
+			.unwrap()
+			.created_block()
+			.unwrap();
+		let elapsed = start.elapsed();
+
+		let timings = timings_rx.next().await.unwrap();
+		let phases = timings.phases.iter().map(|(phase, _)| *phase).collect::<Vec<_>>();
+		assert_eq!(phases, vec![SealPhase::Inherents, SealPhase::Proposal, SealPhase::Import]);
+		// the phases are measured within the handling of the command.
+		let total = timings.phases.iter().map(|(_, duration)| *duration).sum::<Duration>();
+		assert!(total <= elapsed);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_without_inherents() {
+		let inherent_data_providers = InherentDataProviders::new();
+		inherent_data_providers
+			.register_provider(sp_timestamp::InherentDataProvider)
+			.unwrap();
+		let engine = TestChain::new().start(inherent_data_providers, ManualSealConfig::default());
+
+		// inherent data can't be supplied for a block without inherents.
+		assert_matches::assert_matches!(
+			engine.seal(SealParams {
+				create_empty: true,
+				timestamp: Some(1_000),
+				skip_inherents: true,
+				..Default::default()
+			}).await,
+			Err(Error::StringError(_))
+		);
+
+		let created_block = engine
+			.seal(SealParams { create_empty: true, skip_inherents: true, ..Default::default() })
+			.await
+			.unwrap()
+			.created_block()
+			.unwrap();
+
+		assert!(created_block.inherents.is_empty());
+		let header = engine.client.header(&BlockId::Number(1)).unwrap().unwrap();
+		assert_eq!(header.hash(), created_block.hash);
+	}
+
+	#[tokio::test]
+	async fn blocks_without_inherents_fail_inherent_checks() {
+		let engine = start_timestamp_checking_engine();
+		let TestEngine { client, .. } = &engine;
+		let seal_block = |skip_inherents| {
+			engine.seal(SealParams { create_empty: true, skip_inherents, ..Default::default() })
+		};
+
+		// the block lacks the timestamp inherent, and nothing is imported.
+		assert_matches::assert_matches!(seal_block(true).await, Err(Error::InvalidInherents(_)));
+		assert_eq!(client.info().best_number, 0);
+
+		// the same block with its inherents passes the checks.
+		let created_block = seal_block(false).await.unwrap().created_block().unwrap();
+		assert_eq!(client.info().best_hash, created_block.hash);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_with_verbatim_inherent_data() {
+		/// inherent data provider that always fails.
+		struct FailingProvider;
+
+		impl sp_inherents::ProvideInherentData for FailingProvider {
+			fn inherent_identifier(&self) -> &'static sp_inherents::InherentIdentifier {
+				b"failing0"
+			}
+
+			fn provide_inherent_data(
+				&self,
+				_: &mut sp_inherents::InherentData,
+			) -> Result<(), sp_inherents::Error> {
+				Err("no inherent data".into())
+			}
+
+			fn error_to_string(&self, _: &[u8]) -> Option<String> {
+				None
+			}
+		}
+
+		let inherent_data_providers = InherentDataProviders::new();
+		inherent_data_providers.register_provider(FailingProvider).unwrap();
+		let engine = TestChain::new().start(inherent_data_providers, ManualSealConfig::default());
+		let TestEngine { client, .. } = &engine;
+
+		let seal_block = |timestamp, inherent_data| {
+			engine.seal(SealParams {
+				create_empty: true,
+				timestamp,
+				inherent_data,
+				..Default::default()
+			})
+		};
+
+		// the providers fail to create the inherent data.
+		assert_matches::assert_matches!(seal_block(None, None).await, Err(Error::InherentError(_)));
+
+		// the timestamp can't override inherent data used verbatim.
+		let mut inherent_data = sp_inherents::InherentData::new();
+		inherent_data.put_data(sp_timestamp::INHERENT_IDENTIFIER, &1_000u64).unwrap();
+		assert_matches::assert_matches!(
+			seal_block(Some(2_000), Some(inherent_data.clone())).await,
+			Err(Error::StringError(_))
+		);
+
+		// verbatim inherent data bypasses the providers.
+		let created_block = seal_block(None, Some(inherent_data)).await
+			.unwrap()
+			.created_block()
+			.unwrap();
+		let header = client.header(&BlockId::Number(1)).unwrap().unwrap();
+		assert_eq!(header.hash(), created_block.hash);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_terminates_on_fatal_error() {
+		let TestChain { client, select_chain, pool, .. } = TestChain::new();
+		let inherent_data_providers = InherentDataProviders::new();
+		let env = ProposerFactory::new(client.clone(), pool.clone(), None);
+		let (mut sink, stream) = futures::channel::mpsc::channel(1024);
+
+		// building on an unknown parent is made fatal.
+		let (tx, rx) = futures::channel::oneshot::channel();
+		sink.send(EngineCommand::SealNewBlock {
+			params: SealParams {
+				create_empty: true,
+				parent_hash: Some(Default::default()),
+				..Default::default()
+			},
+			sender: Some(tx),
+		}).await.unwrap();
+
+		let result = run_manual_seal(
+			Box::new(client.clone()),
+			env,
+			client.clone(),
+			pool.pool().clone(),
+			stream,
+			select_chain,
+			inherent_data_providers,
+			ManualSealConfig {
+				is_fatal: |err| matches!(err, Error::BlockNotFound(_)),
+				..Default::default()
+			},
+		).await;
+
+		// the task ended with the error, and the command wasn't answered.
+		assert_matches::assert_matches!(result, Err(Error::BlockNotFound(_)));
+		assert!(rx.await.is_err());
+		assert_eq!(client.info().best_number, 0);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_query_head() {
+		let mut engine = start_test_engine(ManualSealConfig::default());
+
+		// seal a finalized block, and a non-finalized one on top of it.
+		let mut created = Vec::new();
+		for finalize in vec![true, false] {
+			let outcome = engine.seal_block(true, finalize).await.unwrap();
+			created.push(outcome.created_block().unwrap().hash);
+		}
+
+		assert_eq!(
+			engine.query_head().await.unwrap(),
+			ChainHead {
+				best_hash: created[1],
+				best_number: 2,
+				finalized_hash: created[0],
+				finalized_number: 1,
+			}
+		);
+	}
+
+	/// environment recording the timestamp inherent of every proposal.
+	struct TimestampRecorder<E> {
+		inner: E,
+		timestamps: Arc<parking_lot::Mutex<Vec<Option<u64>>>>,
+	}
+
+	impl<B: BlockT, E: Environment<B>> Environment<B> for TimestampRecorder<E> {
+		type Proposer = TimestampRecorder<E::Proposer>;
+		type CreateProposer = std::pin::Pin<Box<
+			dyn Future<Output = Result<Self::Proposer, Self::Error>> + Send
+		>>;
+		type Error = E::Error;
+
+		fn init(&mut self, parent_header: &B::Header) -> Self::CreateProposer {
+			let timestamps = self.timestamps.clone();
+			Box::pin(
+				self.inner.init(parent_header).map_ok(|inner| TimestampRecorder { inner, timestamps })
+			)
+		}
+	}
+
+	impl<B: BlockT, P: Proposer<B>> Proposer<B> for TimestampRecorder<P> {
+		type Error = P::Error;
+		type Transaction = P::Transaction;
+		type Proposal = P::Proposal;
+
+		fn propose(
+			self,
+			inherent_data: sp_inherents::InherentData,
+			inherent_digests: sp_runtime::traits::DigestFor<B>,
+			max_duration: Duration,
+			record_proof: sp_consensus::RecordProof,
+		) -> Self::Proposal {
+			let timestamp = inherent_data.get_data(&sp_timestamp::INHERENT_IDENTIFIER).unwrap();
+			self.timestamps.lock().push(timestamp);
+			self.inner.propose(inherent_data, inherent_digests, max_duration, record_proof)
+		}
+	}
+
+	#[tokio::test]
+	async fn manual_seal_with_mock_clock() {
+		let TestChain { client, select_chain, pool, pool_api } = TestChain::new();
+		let inherent_data_providers = InherentDataProviders::new();
+		let timestamps = Arc::new(parking_lot::Mutex::new(Vec::new()));
+		let env = TimestampRecorder {
+			inner: ProposerFactory::new(client.clone(), pool.clone(), None),
+			timestamps: timestamps.clone(),
+		};
+		let (sink, stream) = futures::channel::mpsc::channel(1024);
+		let future = run_manual_seal(
+			Box::new(client.clone()),
+			env,
+			client.clone(),
+			pool.pool().clone(),
+			stream,
+			select_chain,
+			inherent_data_providers,
+			ManualSealConfig::default(),
+		);
+		std::thread::spawn(|| {
+			let mut rt = tokio::runtime::Runtime::new().unwrap();
+			// spawn the background authorship task
+			rt.block_on(future).unwrap();
+		});
+		let engine = TestEngine { client, pool, pool_api, commands: sink };
+
+		let send = |command: fn(rpc::Sender<u64>) -> EngineCommand<_>| {
+			let mut sink = engine.commands.clone();
+			async move {
+				let (tx, rx) = futures::channel::oneshot::channel();
+				sink.send(command(Some(tx))).await.unwrap();
+				rx.await.unwrap().unwrap()
+			}
+		};
+		let seal_block = || {
+			engine.seal(SealParams { create_empty: true, ..Default::default() })
+				.map(|outcome| outcome.unwrap().created_block().unwrap())
+		};
+
+		// without a mock clock, the timestamp is left to the providers.
+		seal_block().await;
+
+		assert_eq!(send(|sender| EngineCommand::SetTime { to: 1_000, sender }).await, 1_000);
+		seal_block().await;
+
+		let advance = |sender| EngineCommand::AdvanceTime { by: Duration::from_millis(500), sender };
+		assert_eq!(send(advance).await, 1_500);
+		seal_block().await;
+
+		// the advances accumulate, and the clock doesn't move on its own.
+		let advance = |sender| EngineCommand::AdvanceTime { by: Duration::from_millis(250), sender };
+		assert_eq!(send(advance).await, 1_750);
+		assert_eq!(send(advance).await, 2_000);
+		seal_block().await;
+		seal_block().await;
+
+		assert_eq!(
+			*timestamps.lock(),
+			vec![None, Some(1_000), Some(1_500), Some(2_000), Some(2_000)],
+		);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_reports_blocks_already_in_chain() {
+		let engine = start_test_engine(ManualSealConfig::default());
+		let genesis = engine.client.info().genesis_hash;
+		let seal_block = || {
+			engine.seal(SealParams {
+				create_empty: true,
+				parent_hash: Some(genesis),
+				..Default::default()
+			}).map(Result::unwrap)
+		};
+
+		// the same empty block on the same parent is only imported once.
+		let created_block = match seal_block().await {
+			SealOutcome::Sealed(created_block) => created_block,
+			outcome => panic!("Expected a new block, got {:?}", outcome),
+		};
+		assert_eq!(seal_block().await, SealOutcome::AlreadyInChain(created_block.hash));
+		assert_eq!(SealOutcome::AlreadyInChain(created_block.hash).created_block(), None);
+	}
+
+	#[test]
+	fn timestamp_inherent_can_be_overridden() {
+		let inherent_data_providers = InherentDataProviders::new();
+		inherent_data_providers
+			.register_provider(sp_timestamp::InherentDataProvider)
+			.unwrap();
+
+		// roughly year 2500, far ahead of the wall clock.
+		let future_timestamp: u64 = 16_725_225_600_000;
+		let inherent_data = seal_new_block::create_inherent_data(
+			&inherent_data_providers,
+			None,
+			Some(future_timestamp),
+		).unwrap();
+		assert_eq!(
+			inherent_data.get_data::<u64>(&sp_timestamp::INHERENT_IDENTIFIER).unwrap(),
+			Some(future_timestamp),
+		);
+
+		// without an override, the provider's value is kept.
+		let inherent_data = seal_new_block::create_inherent_data(&inherent_data_providers, None, None)
+			.unwrap();
+		let timestamp = inherent_data.get_data::<u64>(&sp_timestamp::INHERENT_IDENTIFIER)
+			.unwrap()
+			.unwrap();
+		assert!(timestamp < future_timestamp);
+	}
+
+	#[test]
+	fn extra_inherents_are_merged() {
+		const CUSTOM_IDENTIFIER: sp_inherents::InherentIdentifier = *b"custom00";
+
+		let inherent_data_providers = InherentDataProviders::new();
+		inherent_data_providers
+			.register_provider(sp_timestamp::InherentDataProvider)
+			.unwrap();
+
+		let mut extra_inherents = sp_inherents::InherentData::new();
+		extra_inherents.put_data(CUSTOM_IDENTIFIER, &42u32).unwrap();
+		extra_inherents.put_data(sp_timestamp::INHERENT_IDENTIFIER, &1_000u64).unwrap();
+
+		let inherent_data = seal_new_block::create_inherent_data(
+			&inherent_data_providers,
+			Some(extra_inherents.clone()),
+			None,
+		).unwrap();
+		assert_eq!(inherent_data.get_data::<u32>(&CUSTOM_IDENTIFIER).unwrap(), Some(42));
+		// values of the caller take precedence over the ones of the providers.
+		assert_eq!(
+			inherent_data.get_data::<u64>(&sp_timestamp::INHERENT_IDENTIFIER).unwrap(),
+			Some(1_000),
+		);
+
+		// an explicit timestamp takes precedence over the extra inherents.
+		let inherent_data = seal_new_block::create_inherent_data(
+			&inherent_data_providers,
+			Some(extra_inherents),
+			Some(2_000),
+		).unwrap();
+		assert_eq!(inherent_data.get_data::<u32>(&CUSTOM_IDENTIFIER).unwrap(), Some(42));
+		assert_eq!(
+			inherent_data.get_data::<u64>(&sp_timestamp::INHERENT_IDENTIFIER).unwrap(),
+			Some(2_000),
+		);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_on_parent_number() {
+		let engine = start_test_engine(ManualSealConfig::default());
+		let TestEngine { client, pool, .. } = &engine;
+
+		let seal_block = |parent_number: Option<u64>| {
+			engine.seal(SealParams { create_empty: true, parent_number, ..Default::default() })
+				.map_ok(|outcome| outcome.created_block().unwrap())
+		};
+
+		// build two empty blocks on top of each other.
+		seal_block(None).await.unwrap();
+		seal_block(None).await.unwrap();
+		let first = client.header(&BlockId::Number(1)).unwrap().unwrap();
+
+		// fork off the first block. the transaction makes the new block differ from the
+		// existing block #2.
+		assert!(pool.submit_one(&BlockId::Number(0), SOURCE, uxt(Alice, 0)).await.is_ok());
+		let created_block = seal_block(Some(1)).await.unwrap();
+		let header = client.header(&BlockId::Hash(created_block.hash)).unwrap().unwrap();
+		assert_eq!(header.number, 2);
+		assert_eq!(header.parent_hash, first.hash());
+
+		// a number ahead of the best block can't be built on.
+		assert_matches::assert_matches!(
+			seal_block(Some(5)).await,
+			Err(Error::BlockNumberNotFound(5))
+		);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_rate_limits_seal_commands() {
+		let engine = start_test_engine(ManualSealConfig {
+			min_seal_interval: Some(Duration::from_secs(60)),
+			..Default::default()
+		});
+		let TestEngine { client, .. } = &engine;
+
+		let seal_block = || engine.seal(SealParams { create_empty: true, ..Default::default() });
+
+		assert!(seal_block().await.is_ok());
+		assert_matches::assert_matches!(
+			seal_block().await,
+			Err(Error::TooSoon(delay)) if delay <= Duration::from_secs(60)
+		);
+		// the rejected command didn't create a block.
+		assert_eq!(client.info().best_number, 1);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_rate_limits_only_sealed_blocks() {
+		let engine = start_test_engine(ManualSealConfig {
+			min_seal_interval: Some(Duration::from_secs(60)),
+			..Default::default()
+		});
+		let TestEngine { client, .. } = &engine;
+
+		// the pool is empty, so the first command fails without sealing anything.
+		assert_matches::assert_matches!(
+			engine.seal(SealParams::default()).await,
+			Err(Error::EmptyTransactionPool)
+		);
+
+		// which doesn't hold back the next one.
+		let seal_block = || engine.seal(SealParams { create_empty: true, ..Default::default() });
+		assert!(seal_block().await.is_ok());
+		assert_eq!(client.info().best_number, 1);
+		assert_matches::assert_matches!(seal_block().await, Err(Error::TooSoon(_)));
+	}
+
+	#[tokio::test]
+	async fn manual_seal_with_manual_best() {
+		let engine = start_test_engine(
+			ManualSealConfig { manual_best: true, ..Default::default() },
+		);
+		let TestEngine { client, pool, .. } = &engine;
+
+		let seal_block = |parent_hash: Option<sp_core::H256>, set_best: Option<bool>| {
+			engine.seal(SealParams {
+				create_empty: true,
+				parent_hash,
+				set_best,
+				..Default::default()
+			}).map(|outcome| outcome.unwrap().created_block().unwrap())
+		};
+		let genesis = client.info().genesis_hash;
+
+		// the first fork is two blocks long, yet the best block doesn't change.
+		let a1 = seal_block(None, None).await;
+		let a2 = seal_block(Some(a1.hash), None).await;
+		assert!(!a2.aux.is_new_best);
+		assert_eq!(client.info().best_hash, genesis);
+
+		// the transaction makes the second fork differ from the first one.
+		assert!(pool.submit_one(&BlockId::Number(0), SOURCE, uxt(Alice, 0)).await.is_ok());
+		let b1 = seal_block(Some(genesis), None).await;
+		assert_eq!(client.info().best_hash, genesis);
+
+		// the best block only changes when explicitly asked to.
+		let b2 = seal_block(Some(b1.hash), Some(true)).await;
+		assert!(b2.aux.is_new_best);
+		assert_eq!(client.info().best_hash, b2.hash);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_finalizes_ranges() {
+		let mut engine = start_test_engine(ManualSealConfig::default());
+
+		let mut hashes = Vec::new();
+		for _ in 0..10 {
+			let outcome = engine.seal_block(true, false).await.unwrap();
+			hashes.push(outcome.created_block().unwrap().hash);
+		}
+
+		let TestEngine { client, commands: sink, .. } = &engine;
+		let finalize_range = |from: sp_core::H256, to: sp_core::H256| {
+			let mut sink = sink.clone();
+			async move {
+				let (tx, rx) = futures::channel::oneshot::channel();
+				sink.send(EngineCommand::FinalizeRange {
+					from,
+					to,
+					sender: Some(tx),
+					justification: None,
+				}).await.unwrap();
+				rx.await.unwrap()
+			}
+		};
+
+		// finalize blocks #3 to #7.
+		assert!(finalize_range(hashes[2], hashes[6]).await.is_ok());
+		assert_eq!(client.info().finalized_number, 7);
+		assert_eq!(client.info().finalized_hash, hashes[6]);
+
+		// `from` must be an ancestor of `to`.
+		assert_matches::assert_matches!(
+			finalize_range(hashes[9], hashes[8]).await,
+			Err(Error::StringError(_))
+		);
+		assert_eq!(client.info().finalized_number, 7);
+
+		// blocks that are already finalized are skipped.
+		assert!(finalize_range(hashes[0], hashes[9]).await.is_ok());
+		assert_eq!(client.info().finalized_number, 10);
+		assert_eq!(client.info().finalized_hash, hashes[9]);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_with_fork_choice() {
+		use substrate_test_runtime_client::runtime::Header;
+
+		// even block numbers win over odd ones, then the highest block number wins.
+		fn prefer_even(new: &Header, best: &Header) -> std::cmp::Ordering {
+			(new.number % 2 == 0).cmp(&(best.number % 2 == 0))
+				.then(new.number.cmp(&best.number))
+		}
+
+		let engine = start_test_engine(
+			ManualSealConfig { fork_choice: Some(prefer_even), ..Default::default() },
+		);
+		let TestEngine { client, .. } = &engine;
+
+		let seal_block = |parent_hash: sp_core::H256, set_best: Option<bool>| {
+			engine.seal(SealParams {
+				create_empty: true,
+				parent_hash: Some(parent_hash),
+				set_best,
+				..Default::default()
+			}).map(|outcome| outcome.unwrap().created_block().unwrap())
+		};
+		let genesis = client.info().genesis_hash;
+
+		// odd blocks never replace an even best block, even blocks always replace an odd one.
+		let b1 = seal_block(genesis, None).await;
+		assert!(!b1.aux.is_new_best);
+		assert_eq!(client.info().best_hash, genesis);
+		let b2 = seal_block(b1.hash, None).await;
+		assert!(b2.aux.is_new_best);
+		assert_eq!(client.info().best_hash, b2.hash);
+		let b3 = seal_block(b2.hash, None).await;
+		assert_eq!(client.info().best_hash, b2.hash);
+		let b4 = seal_block(b3.hash, None).await;
+		assert_eq!(client.info().best_hash, b4.hash);
+
+		// `set_best` still overrides the comparator.
+		let b5 = seal_block(b4.hash, Some(true)).await;
+		assert_eq!(client.info().best_hash, b5.hash);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_with_seal_predicate() {
+		let allowed = Arc::new(std::sync::atomic::AtomicBool::new(false));
+		let seal_predicate = {
+			let allowed = allowed.clone();
+			SealPredicate::new(move |_| allowed.load(Ordering::SeqCst))
+		};
+		let engine = start_test_engine(
+			ManualSealConfig { seal_predicate: Some(seal_predicate), ..Default::default() },
+		);
+		let TestEngine { client, .. } = &engine;
+
+		let seal_block = || engine.seal(SealParams { create_empty: true, ..Default::default() });
+		let genesis = client.info().genesis_hash;
+
+		// the predicate refuses sealing, no block is produced.
+		assert_matches::assert_matches!(
+			seal_block().await,
+			Err(Error::SealRefused(hash)) if hash == format!("{}", genesis)
+		);
+		assert_eq!(client.info().best_number, 0);
+
+		// once allowed, blocks are sealed again.
+		allowed.store(true, Ordering::SeqCst);
+		assert!(seal_block().await.is_ok());
+		assert_eq!(client.info().best_number, 1);
+	}
+
+	#[tokio::test]
+	async fn pool_command_stream_seals_imported_transactions() {
+		let TestChain { pool, .. } = TestChain::new();
+		let mut commands = pool_command_stream(pool.pool());
+
+		assert!(pool.submit_one(&BlockId::Number(0), SOURCE, uxt(Alice, 0)).await.is_ok());
+
+		assert_matches::assert_matches!(
+			commands.next().await,
+			Some(EngineCommand::SealNewBlock {
+				params: SealParams { create_empty: false, finalize: false, .. },
+				sender: None,
+			})
+		);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_waits_for_transactions() {
+		let engine = start_test_engine(ManualSealConfig::default());
+		let TestEngine { client, pool, .. } = &engine;
+
+		let seal_block = |empty_pool_wait: Duration| {
+			engine.seal(SealParams { empty_pool_wait: Some(empty_pool_wait), ..Default::default() })
+		};
+
+		// nothing arrives within the grace window.
+		assert_matches::assert_matches!(
+			seal_block(Duration::from_millis(100)).await,
+			Err(Error::EmptyTransactionPool)
+		);
+		assert_eq!(client.info().best_number, 0);
+
+		// a transaction submitted after the seal command, within the grace window, is sealed.
+		let submit = async {
+			futures_timer::Delay::new(Duration::from_millis(100)).await;
+			pool.submit_one(&BlockId::Number(0), SOURCE, uxt(Alice, 0)).await.unwrap()
+		};
+		let (outcome, alice) = futures::join!(seal_block(Duration::from_secs(30)), submit);
+		let created_block = outcome.unwrap().created_block().unwrap();
+		assert_eq!(created_block.transactions, vec![alice]);
+		assert_eq!(client.info().best_number, 1);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_replays_recorded_commands() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("commands");
+
+		// starts an engine on a new chain, returns its client and command sink.
+		let start_engine = |record_to: Option<&std::path::Path>| {
+			let TestChain { client, select_chain, pool, .. } = TestChain::new();
+			let env = ProposerFactory::new(client.clone(), pool.clone(), None);
+			let (sink, stream) = futures::channel::mpsc::channel(1024);
+			let stream = match record_to {
+				Some(path) => record::record(stream, path).unwrap().boxed(),
+				None => stream.boxed(),
+			};
+			let future = run_manual_seal(
+				Box::new(client.clone()),
+				env,
+				client.clone(),
+				pool.pool().clone(),
+				stream,
+				select_chain,
+				InherentDataProviders::new(),
+				ManualSealConfig::default(),
+			);
+			std::thread::spawn(|| {
+				let mut rt = tokio::runtime::Runtime::new().unwrap();
+				// spawn the background authorship task
+				rt.block_on(future).unwrap();
+			});
+			(client, sink)
+		};
+
+		// seal a few blocks and finalize some of them, while recording.
+		let (client, mut sink) = start_engine(Some(path.as_path()));
+		for _ in 0..3 {
+			let (tx, rx) = futures::channel::oneshot::channel();
+			sink.send(EngineCommand::SealNewBlock {
+				params: SealParams { create_empty: true, ..Default::default() },
+				sender: Some(tx),
+			}).await.unwrap();
+			rx.await.unwrap().unwrap();
+		}
+		let (tx, rx) = futures::channel::oneshot::channel();
+		sink.send(EngineCommand::FinalizeBlock {
+			hash: client.hash(2).unwrap().unwrap(),
+			sender: Some(tx),
+			justification: None,
+		}).await.unwrap();
+		rx.await.unwrap().unwrap();
+
+		// the same commands on a new chain produce the same chain.
+		let (replayed_client, mut replayed_sink) = start_engine(None);
+		let results = record::replay(&path, &mut replayed_sink).await.unwrap();
+		assert_eq!(results.len(), 4);
+		assert!(results.iter().all(Result::is_ok));
+		assert_eq!(replayed_client.info().best_hash, client.info().best_hash);
+		assert_eq!(replayed_client.info().finalized_hash, client.info().finalized_hash);
+		for number in 1..=3 {
+			assert_eq!(replayed_client.hash(number).unwrap(), client.hash(number).unwrap());
+		}
+	}
+
+	#[tokio::test]
+	async fn manual_seal_with_inherents_validation() {
+		let engine = start_test_engine(
+			ManualSealConfig { validate_inherents: true, ..Default::default() },
+		);
+		let TestEngine { client, pool, .. } = &engine;
+		let alice = pool.submit_one(&BlockId::Number(0), SOURCE, uxt(Alice, 0)).await.unwrap();
+
+		// the test runtime accepts the inherents of any block, so the block is imported.
+		let outcome = engine.seal(SealParams::default()).await.unwrap();
+		let created_block = outcome.created_block().unwrap();
+		assert_eq!(created_block.transactions, vec![alice]);
+		assert_eq!(client.info().best_hash, created_block.hash);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_rejects_invalid_inherents() {
+		let engine = start_timestamp_checking_engine();
+		let TestEngine { client, .. } = &engine;
+
+		// roughly year 2500, far ahead of the wall clock.
+		let timestamp = Some(16_725_225_600_000);
+		assert_matches::assert_matches!(
+			engine.seal(SealParams { create_empty: true, timestamp, ..Default::default() }).await,
+			Err(Error::InvalidInherents(_))
+		);
+		// nothing was imported.
+		assert_eq!(client.info().best_number, 0);
+		assert!(client.header(&BlockId::Number(1)).unwrap().is_none());
+	}
+
+	#[tokio::test]
+	async fn manual_seal_with_random_fork() {
+		// seals blocks on a new chain, returns the number of every block proposed.
+		let seal_blocks = |random_fork: RandomFork| async move {
+			let mut engine = start_test_engine(
+				ManualSealConfig { random_fork: Some(random_fork), ..Default::default() },
+			);
+
+			let mut numbers = Vec::new();
+			for _ in 0..10 {
+				// empty blocks built on a block that already has a child are that child.
+				let hash = match engine.seal_block(true, false).await.unwrap() {
+					SealOutcome::Sealed(created_block) => created_block.hash,
+					SealOutcome::AlreadyInChain(hash) => hash,
+				};
+				numbers.push(engine.client.number(hash).unwrap().unwrap());
+			}
+			numbers
+		};
+
+		let random_fork = RandomFork { seed: 42, max_depth: 2 };
+		let numbers = seal_blocks(random_fork).await;
+		assert_eq!(seal_blocks(random_fork).await, numbers);
+
+		// some blocks aren't built on the best block.
+		assert!(numbers.windows(2).any(|pair| pair[1] <= pair[0]));
+
+		// without depth, every block is built on the best block.
+		let numbers = seal_blocks(RandomFork { seed: 42, max_depth: 0 }).await;
+		assert_eq!(numbers, (1..=10).collect::<Vec<_>>());
+	}
+
+	#[tokio::test]
+	async fn manual_seal_with_extra_digest_items() {
+		use sp_runtime::generic::DigestItem;
+		let engine = start_test_engine(ManualSealConfig::default());
+		let TestEngine { client, .. } = &engine;
+
+		let seal_block = |extra_digest_items| {
+			engine.seal(SealParams { create_empty: true, extra_digest_items, ..Default::default() })
+		};
+
+		// two pre-runtime digests of the same engine are refused.
+		let conflicting = vec![
+			DigestItem::PreRuntime(*b"test", vec![1]),
+			DigestItem::PreRuntime(*b"test", vec![2]),
+		];
+		assert_matches::assert_matches!(seal_block(conflicting).await, Err(Error::StringError(_)));
+		assert_eq!(client.info().best_number, 0);
+
+		let items = vec![
+			DigestItem::PreRuntime(*b"test", vec![1]),
+			DigestItem::PreRuntime(*b"othr", vec![2]),
+			DigestItem::Other(vec![3]),
+		];
+		let created_block = seal_block(items.clone()).await.unwrap().created_block().unwrap();
+		let header = client.header(BlockId::Hash(created_block.hash)).unwrap().unwrap();
+		for item in &items {
+			assert!(header.digest.logs.contains(item), "{:?} is missing from the header", item);
+		}
+	}
+
+	#[tokio::test]
+	async fn manual_seal_until_transaction_included() {
+		let TestEngine { client, pool, commands: sink, .. } = start_test_engine(
+			ManualSealConfig::default(),
+		);
+		let seal_until_included = |tx_hash: sp_core::H256, max_blocks: u32| {
+			let mut sink = sink.clone();
+			async move {
+				let (tx, rx) = futures::channel::oneshot::channel();
+				sink.send(EngineCommand::SealUntilIncluded {
+					tx_hash,
+					max_blocks,
+					sender: Some(tx),
+				}).await.unwrap();
+				rx.await.unwrap()
+			}
+		};
+
+		let alice = pool.submit_one(&BlockId::Number(0), SOURCE, uxt(Alice, 0)).await.unwrap();
+		let hash = seal_until_included(alice, 3).await.unwrap();
+		assert_eq!(client.info().best_hash, hash);
+		assert_eq!(client.info().best_number, 1);
+
+		// nothing is sealed without any blocks to spare.
+		let bob = pool.submit_one(&BlockId::Number(1), SOURCE, uxt(Bob, 0)).await.unwrap();
+		assert_matches::assert_matches!(
+			seal_until_included(bob, 0).await,
+			Err(Error::TransactionNotIncluded(_, 0))
+		);
+		assert_eq!(client.info().best_number, 1);
+
+		// alice's transaction is already in the chain: bob's is sealed, then the pool runs dry.
+		assert_matches::assert_matches!(
+			seal_until_included(alice, 3).await,
+			Err(Error::EmptyTransactionPool)
+		);
+		assert_eq!(client.info().best_number, 2);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_with_justification() {
+		let engine = start_test_engine(ManualSealConfig::default());
+		let TestEngine { client, .. } = &engine;
+		let seal_block = |finalize: bool, justification: Option<Justification>| {
+			engine.seal(SealParams {
+				create_empty: true,
+				finalize,
+				justification,
+				..Default::default()
+			})
+		};
+
+		let created_block = seal_block(true, Some(vec![1, 2, 3])).await
+			.unwrap()
+			.created_block()
+			.unwrap();
+		assert_eq!(client.info().finalized_hash, created_block.hash);
+		assert_eq!(
+			client.justification(&BlockId::Hash(created_block.hash)).unwrap(),
+			Some(vec![1, 2, 3]),
+		);
+
+		// justifications are only accepted for finalized blocks, and must not be empty.
+		assert_matches::assert_matches!(
+			seal_block(false, Some(vec![1, 2, 3])).await,
+			Err(Error::StringError(_))
+		);
+		assert_matches::assert_matches!(
+			seal_block(true, Some(Vec::new())).await,
+			Err(Error::StringError(_))
+		);
+		assert_eq!(client.info().best_number, 1);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_reports_block_size() {
+		let engine = start_test_engine(ManualSealConfig::default());
+		let TestEngine { client, pool, .. } = &engine;
+		let seal_block = || {
+			engine.seal(SealParams { create_empty: true, ..Default::default() })
+				.map(|outcome| outcome.unwrap().created_block().unwrap())
+		};
+		let imported_size = |hash| {
+			client.block(&BlockId::Hash(hash)).unwrap().unwrap().block.encoded_size()
+		};
+
+		let empty_block = seal_block().await;
+		assert_eq!(empty_block.size, imported_size(empty_block.hash));
+
+		let transaction = uxt(Alice, 0);
+		let transaction_size = transaction.encoded_size();
+		assert!(pool.submit_one(&BlockId::Number(1), SOURCE, transaction).await.is_ok());
+		let block = seal_block().await;
+		assert_eq!(block.transactions.len(), 1);
+		assert_eq!(block.size, imported_size(block.hash));
+		assert!(block.size >= empty_block.size + transaction_size);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_with_frozen_clock() {
+		async fn seal_blocks() -> (Vec<sp_core::H256>, Vec<Option<u64>>) {
+			let TestChain { client, select_chain, pool, pool_api } = TestChain::new();
+			let inherent_data_providers = InherentDataProviders::new();
+			let timestamps = Arc::new(parking_lot::Mutex::new(Vec::new()));
+			let env = TimestampRecorder {
+				inner: ProposerFactory::new(client.clone(), pool.clone(), None),
+				timestamps: timestamps.clone(),
+			};
+			let (sink, stream) = futures::channel::mpsc::channel(1024);
+			let future = run_manual_seal(
+				Box::new(client.clone()),
+				env,
+				client.clone(),
+				pool.pool().clone(),
+				stream,
+				select_chain,
+				inherent_data_providers,
+				ManualSealConfig { frozen_clock: Some(1_000), ..Default::default() },
+			);
+			std::thread::spawn(|| {
+				let mut rt = tokio::runtime::Runtime::new().unwrap();
+				// spawn the background authorship task
+				rt.block_on(future).unwrap();
+			});
+			let mut engine = TestEngine { client, pool, pool_api, commands: sink };
+
+			let mut hashes = Vec::new();
+			for _ in 0..3 {
+				let outcome = engine.seal_block(true, false).await.unwrap();
+				hashes.push(outcome.created_block().unwrap().hash);
+
+				let (tx, rx) = futures::channel::oneshot::channel();
+				engine.commands.send(EngineCommand::AdvanceTime {
+					by: Duration::from_millis(500),
+					sender: Some(tx),
+				}).await.unwrap();
+				rx.await.unwrap().unwrap();
+			}
+
+			let timestamps = timestamps.lock().clone();
+			(hashes, timestamps)
+		}
+
+		let (hashes, timestamps) = seal_blocks().await;
+		assert_eq!(timestamps, vec![Some(1_000), Some(1_500), Some(2_000)]);
+
+		// the wall clock has moved, but the blocks are the same.
+		std::thread::sleep(Duration::from_millis(10));
+		assert_eq!(seal_blocks().await, (hashes, timestamps));
+	}
+
+	#[tokio::test]
+	async fn manual_seal_exports_best_chain() {
+		let engine = start_test_engine(ManualSealConfig::default());
+		let TestEngine { client, commands: sink, .. } = &engine;
+		let seal_block = |parent_hash: Option<sp_core::H256>, finalize: bool| {
+			engine.seal(SealParams {
+				create_empty: true,
+				finalize,
+				parent_hash,
+				..Default::default()
+			}).map(|outcome| outcome.unwrap().created_block().unwrap().hash)
+		};
+		let export_chain = |from: u64, to: u64| {
+			let mut sink = sink.clone();
+			async move {
+				let (tx, rx) = futures::channel::oneshot::channel();
+				sink.send(EngineCommand::ExportChain { from, to, sender: Some(tx) }).await.unwrap();
+				rx.await.unwrap()
+			}
+		};
+
+		// a finalized block, then two blocks on top of it and a shorter fork.
+		let block1 = seal_block(None, true).await;
+		let block2 = seal_block(None, false).await;
+		let block3 = seal_block(None, false).await;
+		seal_block(Some(block1), false).await;
+
+		let genesis = client.info().genesis_hash;
+		let entry = |hash, number, finalized| ChainEntry { hash, number, finalized };
+		assert_eq!(
+			export_chain(0, 3).await.unwrap(),
+			vec![
+				entry(genesis, 0, true),
+				entry(block1, 1, true),
+				entry(block2, 2, false),
+				entry(block3, 3, false),
+			],
+		);
+		assert_eq!(export_chain(2, 2).await.unwrap(), vec![entry(block2, 2, false)]);
+
+		assert_matches::assert_matches!(export_chain(2, 4).await, Err(Error::BlockNumberNotFound(4)));
+		assert_matches::assert_matches!(export_chain(3, 2).await, Err(Error::StringError(_)));
+	}
+
+	/// environment building every proposal on the same parent, whatever the parent it is given.
+	struct FixedParent<E, H> {
+		inner: E,
+		parent: H,
+	}
+
+	impl<B: BlockT, E: Environment<B>> Environment<B> for FixedParent<E, B::Header> {
+		type Proposer = E::Proposer;
+		type CreateProposer = E::CreateProposer;
+		type Error = E::Error;
+
+		fn init(&mut self, _: &B::Header) -> Self::CreateProposer {
+			self.inner.init(&self.parent)
+		}
+	}
+
+	#[tokio::test]
+	async fn manual_seal_rejects_proposals_on_wrong_parent() {
+		let TestChain { client, select_chain, pool, pool_api } = TestChain::new();
+		let inherent_data_providers = InherentDataProviders::new();
+		let genesis = client.header(&BlockId::Number(0)).unwrap().unwrap();
+		let env = FixedParent {
+			inner: ProposerFactory::new(client.clone(), pool.clone(), None),
+			parent: genesis,
+		};
+		let (sink, stream) = futures::channel::mpsc::channel(1024);
+		let future = run_manual_seal(
+			Box::new(client.clone()),
+			env,
+			client.clone(),
+			pool.pool().clone(),
+			stream,
+			select_chain,
+			inherent_data_providers,
+			ManualSealConfig::default(),
+		);
+		std::thread::spawn(|| {
+			let mut rt = tokio::runtime::Runtime::new().unwrap();
+			// spawn the background authorship task
+			rt.block_on(future).unwrap();
+		});
+		let engine = TestEngine { client: client.clone(), pool, pool_api, commands: sink };
+		let seal_block = || engine.seal(SealParams { create_empty: true, ..Default::default() });
+
+		// the first block is meant to be built on genesis anyway.
+		let created_block = seal_block().await.unwrap().created_block().unwrap();
+		assert_eq!(client.info().best_hash, created_block.hash);
+
+		// the next one is built on genesis instead of the best block.
+		assert_matches::assert_matches!(seal_block().await, Err(Error::StringError(_)));
+		assert_eq!(client.info().best_hash, created_block.hash);
+		assert_eq!(client.info().best_number, 1);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_imports_external_block() {
+		let TestEngine { client, pool, commands: sink, .. } = start_test_engine(
+			ManualSealConfig::default(),
+		);
+		let import_block = |header: Vec<u8>, body: Vec<Vec<u8>>| {
+			let mut sink = sink.clone();
+			async move {
+				let (tx, rx) = futures::channel::oneshot::channel();
+				sink.send(EngineCommand::ImportBlock {
+					header,
+					body,
+					finalize: false,
+					sender: Some(tx),
+				}).await.unwrap();
+				rx.await.unwrap()
+			}
+		};
+
+		// a block built on genesis outside of the engine, with a digest of its own.
+		let mut factory = ProposerFactory::new(client.clone(), pool.clone(), None);
+		let genesis = client.header(BlockId::Number(0)).unwrap().unwrap();
+		let digest = sp_runtime::generic::Digest {
+			logs: vec![sp_runtime::generic::DigestItem::Other(vec![1, 2, 3])],
+		};
+		let proposer = factory.init(&genesis).await.unwrap();
+		let inherent_data = sp_inherents::InherentData::new();
+		let proposal = proposer.propose(inherent_data, digest, Duration::from_secs(10), false.into())
+			.await
+			.unwrap();
+		let (header, body) = proposal.block.deconstruct();
+		let encoded_body = body.iter().map(Encode::encode).collect::<Vec<_>>();
+
+		assert_matches::assert_matches!(
+			import_block(header.encode(), encoded_body.clone()).await,
+			Ok(ImportResult::Imported(_))
+		);
+		// the block landed on chain as built, and became the best block.
+		assert_eq!(client.info().best_hash, header.hash());
+		assert_eq!(client.header(BlockId::Hash(header.hash())).unwrap(), Some(header.clone()));
+
+		assert_matches::assert_matches!(
+			import_block(header.encode(), encoded_body).await,
+			Ok(ImportResult::AlreadyInChain)
+		);
+		assert_matches::assert_matches!(
+			import_block(vec![1, 2, 3], Vec::new()).await,
+			Err(Error::StringError(_))
+		);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_with_injected_failures() {
+		let engine = start_test_engine(ManualSealConfig::default());
+		let TestEngine { client, commands: sink, .. } = &engine;
+
+		let (tx, rx) = futures::channel::oneshot::channel();
+		sink.clone().send(EngineCommand::InjectFailure {
+			count: 2,
+			error: || Error::StringError("injected".into()),
+			sender: Some(tx),
+		}).await.unwrap();
+		rx.await.unwrap().unwrap();
+
+		let seal_block = || engine.seal(SealParams { create_empty: true, ..Default::default() });
+
+		// exactly two seals fail, without sealing anything.
+		for _ in 0..2 {
+			assert_matches::assert_matches!(
+				seal_block().await,
+				Err(Error::StringError(message)) if message == "injected"
+			);
+		}
+		assert_eq!(client.info().best_number, 0);
+
+		// then sealing works again.
+		let created_block = seal_block().await.unwrap().created_block().unwrap().hash;
+		assert_eq!(client.info().best_hash, created_block);
+		assert_eq!(client.info().best_number, 1);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_with_overridden_state_root() {
+		let engine = start_test_engine(ManualSealConfig::default());
+		let state_root = sp_core::H256::repeat_byte(0x42);
+
+		let outcome = engine.seal(SealParams {
+			create_empty: true,
+			state_root: Some(state_root),
+			..Default::default()
+		}).await.unwrap();
+		let created_block = outcome.created_block().unwrap();
+
+		// the block is imported as sealed, with the overridden state root.
+		let header = engine.client.header(BlockId::Hash(created_block.hash)).unwrap().unwrap();
+		assert_eq!(header.state_root, state_root);
+		assert_eq!(engine.client.info().best_hash, created_block.hash);
+	}
+
+	#[tokio::test]
+	async fn manual_seal_reports_skipped_transactions() {
+		let engine = start_test_engine(ManualSealConfig::default());
+		let TestEngine { pool, .. } = &engine;
+
+		// the third transaction fills the block up, so that it and the following ones don't fit.
+		let exhausting = substrate_test_runtime_client::runtime::Transfer {
+			from: Alice.into(),
+			to: Default::default(),
+			amount: 1,
+			nonce: 2,
+		}.into_resources_exhausting_tx();
+		for xt in vec![uxt(Alice, 0), uxt(Alice, 1), exhausting, uxt(Alice, 3)] {
+			assert!(pool.submit_one(&BlockId::Number(0), SOURCE, xt).await.is_ok());
+		}
+
+		let outcome = engine.seal(SealParams::default()).await.unwrap();
+		let created_block = outcome.created_block().unwrap();
+
+		let selection = created_block.selection;
+		assert_eq!(selection.considered, 4);
+		assert_eq!(selection.included, created_block.transactions.len());
+		assert!(selection.skipped > 0);
+		assert_eq!(selection.included + selection.skipped, selection.considered);
+	}
 }
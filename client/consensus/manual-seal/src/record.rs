@@ -0,0 +1,358 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2020 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Recording and replay of the commands sent to the engine, e.g. to reproduce a test failure.
+//!
+//! A record is a file containing a tag and the version of the record format, followed by the
+//! SCALE-encoded commands, in the order the engine received them. Records of other versions are
+//! rejected. The senders of the commands aren't recorded, replayed commands are given new ones.
+//! Injected failures aren't recorded either, as their errors can't be encoded.
+
+use crate::{rpc::{self, EngineCommand, SealParams}, Error};
+use codec::{Decode, Encode};
+use futures::{channel::{mpsc, oneshot}, prelude::*};
+use sp_inherents::InherentData;
+use sp_runtime::{generic::DigestItem, Justification};
+use std::{convert::TryFrom, fs, io::Write, path::Path, time::Duration};
+
+/// tag at the start of every record, followed by the version of its format.
+const RECORD_TAG: [u8; 4] = *b"seal";
+
+/// version of the record format, to be bumped whenever `RecordedCommand` changes.
+const RECORD_VERSION: u32 = 1;
+
+/// command as stored in a record, see `EngineCommand`.
+#[derive(Encode, Decode)]
+enum RecordedCommand<Hash> {
+	SealNewBlock {
+		create_empty: bool,
+		finalize: bool,
+		parent_hash: Option<Hash>,
+		parent_number: Option<u64>,
+		timestamp: Option<u64>,
+		set_best: Option<bool>,
+		extra_inherents: Option<InherentData>,
+		skip_inherents: bool,
+		inherent_data: Option<InherentData>,
+		max_extrinsics: Option<u64>,
+		/// in nanoseconds.
+		empty_pool_wait: Option<u64>,
+		state_root: Option<Hash>,
+		extra_digest_items: Vec<DigestItem<Hash>>,
+		justification: Option<Justification>,
+	},
+	FinalizeBlock {
+		hash: Hash,
+		justification: Option<Justification>,
+	},
+	FinalizeRange {
+		from: Hash,
+		to: Hash,
+		justification: Option<Justification>,
+	},
+	QueryHead,
+	AdvanceTime {
+		/// in nanoseconds.
+		by: u64,
+	},
+	SetTime {
+		to: u64,
+	},
+	SealUntilIncluded {
+		tx_hash: Hash,
+		max_blocks: u32,
+	},
+	ExportChain {
+		from: u64,
+		to: u64,
+	},
+	ImportBlock {
+		header: Vec<u8>,
+		body: Vec<Vec<u8>>,
+		finalize: bool,
+	},
+}
+
+fn nanos(duration: Duration) -> u64 {
+	u64::try_from(duration.as_nanos()).unwrap_or(u64::max_value())
+}
+
+impl<Hash: Clone> RecordedCommand<Hash> {
+	/// returns the command as stored in a record, or `None` if the command isn't recorded.
+	fn new(command: &EngineCommand<Hash>) -> Option<Self> {
+		Some(match command {
+			EngineCommand::SealNewBlock {
+				params: SealParams {
+					create_empty,
+					finalize,
+					parent_hash,
+					parent_number,
+					timestamp,
+					set_best,
+					extra_inherents,
+					#[cfg(any(test, feature = "test-helpers"))]
+					skip_inherents,
+					inherent_data,
+					max_extrinsics,
+					empty_pool_wait,
+					#[cfg(any(test, feature = "test-helpers"))]
+					state_root,
+					extra_digest_items,
+					justification,
+				},
+				..
+			} => RecordedCommand::SealNewBlock {
+				create_empty: *create_empty,
+				finalize: *finalize,
+				parent_hash: parent_hash.clone(),
+				parent_number: *parent_number,
+				timestamp: *timestamp,
+				set_best: *set_best,
+				extra_inherents: extra_inherents.clone(),
+				#[cfg(any(test, feature = "test-helpers"))]
+				skip_inherents: *skip_inherents,
+				#[cfg(not(any(test, feature = "test-helpers")))]
+				skip_inherents: false,
+				inherent_data: inherent_data.clone(),
+				max_extrinsics: max_extrinsics.map(|max| max as u64),
+				empty_pool_wait: empty_pool_wait.map(nanos),
+				#[cfg(any(test, feature = "test-helpers"))]
+				state_root: state_root.clone(),
+				#[cfg(not(any(test, feature = "test-helpers")))]
+				state_root: None,
+				extra_digest_items: extra_digest_items.clone(),
+				justification: justification.clone(),
+			},
+			EngineCommand::FinalizeBlock { hash, justification, .. } => {
+				RecordedCommand::FinalizeBlock {
+					hash: hash.clone(),
+					justification: justification.clone(),
+				}
+			},
+			EngineCommand::FinalizeRange { from, to, justification, .. } => {
+				RecordedCommand::FinalizeRange {
+					from: from.clone(),
+					to: to.clone(),
+					justification: justification.clone(),
+				}
+			},
+			EngineCommand::QueryHead { .. } => RecordedCommand::QueryHead,
+			EngineCommand::AdvanceTime { by, .. } => RecordedCommand::AdvanceTime { by: nanos(*by) },
+			EngineCommand::SetTime { to, .. } => RecordedCommand::SetTime { to: *to },
+			EngineCommand::SealUntilIncluded { tx_hash, max_blocks, .. } => {
+				RecordedCommand::SealUntilIncluded {
+					tx_hash: tx_hash.clone(),
+					max_blocks: *max_blocks,
+				}
+			},
+			EngineCommand::ExportChain { from, to, .. } => {
+				RecordedCommand::ExportChain { from: *from, to: *to }
+			},
+			EngineCommand::ImportBlock { header, body, finalize, .. } => {
+				RecordedCommand::ImportBlock {
+					header: header.clone(),
+					body: body.clone(),
+					finalize: *finalize,
+				}
+			},
+			#[cfg(any(test, feature = "test-helpers"))]
+			EngineCommand::InjectFailure { .. } => return None,
+		})
+	}
+}
+
+/// records the commands of `commands` to a new record at `path` as they go through.
+///
+/// commands that can't be written to the record are still passed on, and the failure is logged.
+pub fn record<Hash, S>(
+	commands: S,
+	path: impl AsRef<Path>,
+) -> Result<impl Stream<Item=EngineCommand<Hash>>, Error>
+	where
+		Hash: Encode + Clone,
+		S: Stream<Item=EngineCommand<Hash>>,
+{
+	let mut file = fs::File::create(path)
+		.and_then(|mut file| file.write_all(&(RECORD_TAG, RECORD_VERSION).encode()).map(|_| file))
+		.map_err(|err| Error::Other(Box::new(err)))?;
+
+	Ok(commands.map(move |command| {
+		if let Some(recorded) = RecordedCommand::new(&command) {
+			if let Err(err) = file.write_all(&recorded.encode()).and_then(|_| file.flush()) {
+				log::warn!("Failed to record engine command: {}", err);
+			}
+		}
+		command
+	}))
+}
+
+/// sends the commands of the record at `path` to the engine through `sink`.
+///
+/// each command is sent once the engine is done with the previous one, so that the engine goes
+/// through the same sequence as when the record was made. returns the result of every command, in
+/// order, or an error if the record can't be read or the engine has terminated.
+pub async fn replay<Hash>(
+	path: impl AsRef<Path>,
+	sink: &mut mpsc::Sender<EngineCommand<Hash>>,
+) -> Result<Vec<Result<(), Error>>, Error>
+	where
+		Hash: Decode,
+{
+	let record = fs::read(path).map_err(|err| Error::Other(Box::new(err)))?;
+	let mut input = &record[..];
+	match <([u8; 4], u32)>::decode(&mut input) {
+		Ok((RECORD_TAG, RECORD_VERSION)) => {},
+		Ok((RECORD_TAG, version)) => return Err(Error::StringError(format!(
+			"Unsupported engine command record version {}, expected {}", version, RECORD_VERSION
+		))),
+		_ => return Err(Error::StringError(
+			"Not an engine command record, or one made before records were versioned".into()
+		)),
+	}
+
+	let mut results = Vec::new();
+
+	while !input.is_empty() {
+		let recorded = RecordedCommand::<Hash>::decode(&mut input)
+			.map_err(|err| Error::StringError(format!("Invalid engine command record: {}", err)))?;
+
+		let result = match recorded {
+			RecordedCommand::SealNewBlock {
+				create_empty,
+				finalize,
+				parent_hash,
+				parent_number,
+				timestamp,
+				set_best,
+				extra_inherents,
+				skip_inherents,
+				inherent_data,
+				max_extrinsics,
+				empty_pool_wait,
+				state_root,
+				extra_digest_items,
+				justification,
+			} => {
+				#[cfg(not(any(test, feature = "test-helpers")))]
+				{
+					if state_root.is_some() || skip_inherents {
+						return Err(Error::StringError(
+							"Replaying this record requires the `test-helpers` feature".into()
+						))
+					}
+				}
+				send(sink, |sender| EngineCommand::SealNewBlock {
+					params: SealParams {
+						create_empty,
+						finalize,
+						parent_hash,
+						parent_number,
+						timestamp,
+						set_best,
+						extra_inherents,
+						#[cfg(any(test, feature = "test-helpers"))]
+						skip_inherents,
+						inherent_data,
+						max_extrinsics: max_extrinsics.map(|max| max as usize),
+						empty_pool_wait: empty_pool_wait.map(Duration::from_nanos),
+						#[cfg(any(test, feature = "test-helpers"))]
+						state_root,
+						extra_digest_items,
+						justification,
+					},
+					sender,
+				}).await?
+			},
+			RecordedCommand::FinalizeBlock { hash, justification } => {
+				send(sink, |sender| EngineCommand::FinalizeBlock { hash, sender, justification })
+					.await?
+			},
+			RecordedCommand::FinalizeRange { from, to, justification } => {
+				send(sink, |sender| EngineCommand::FinalizeRange { from, to, sender, justification })
+					.await?
+			},
+			RecordedCommand::QueryHead => {
+				send(sink, |sender| EngineCommand::QueryHead { sender }).await?
+			},
+			RecordedCommand::AdvanceTime { by } => {
+				let by = Duration::from_nanos(by);
+				send(sink, |sender| EngineCommand::AdvanceTime { by, sender }).await?
+			},
+			RecordedCommand::SetTime { to } => {
+				send(sink, |sender| EngineCommand::SetTime { to, sender }).await?
+			},
+			RecordedCommand::SealUntilIncluded { tx_hash, max_blocks } => {
+				send(sink, |sender| EngineCommand::SealUntilIncluded { tx_hash, max_blocks, sender })
+					.await?
+			},
+			RecordedCommand::ExportChain { from, to } => {
+				send(sink, |sender| EngineCommand::ExportChain { from, to, sender }).await?
+			},
+			RecordedCommand::ImportBlock { header, body, finalize } => {
+				send(sink, |sender| EngineCommand::ImportBlock { header, body, finalize, sender })
+					.await?
+			},
+		};
+		results.push(result);
+	}
+
+	Ok(results)
+}
+
+/// sends the command built by `command` with a new sender, and waits for its result.
+async fn send<Hash, T>(
+	sink: &mut mpsc::Sender<EngineCommand<Hash>>,
+	command: impl FnOnce(rpc::Sender<T>) -> EngineCommand<Hash>,
+) -> Result<Result<(), Error>, Error> {
+	let (sender, receiver) = oneshot::channel();
+	sink.send(command(Some(sender))).await?;
+
+	Ok(receiver.await?.map(|_| ()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_core::H256;
+
+	fn replay_record(record: &[u8]) -> Result<Vec<Result<(), Error>>, Error> {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("commands");
+		fs::write(&path, record).unwrap();
+		let (mut sink, _stream) = mpsc::channel(1024);
+		futures::executor::block_on(replay::<H256>(&path, &mut sink))
+	}
+
+	#[test]
+	fn rejects_records_of_other_versions() {
+		let command = RecordedCommand::<H256>::QueryHead.encode();
+
+		// a record made before records were versioned.
+		assert_matches::assert_matches!(replay_record(&command), Err(Error::StringError(_)));
+
+		let mut record = (RECORD_TAG, RECORD_VERSION + 1).encode();
+		record.extend(&command);
+		assert_matches::assert_matches!(replay_record(&record), Err(Error::StringError(_)));
+	}
+
+	#[test]
+	fn replays_empty_records() {
+		let record = (RECORD_TAG, RECORD_VERSION).encode();
+		assert!(replay_record(&record).unwrap().is_empty());
+	}
+}
@@ -15,7 +15,7 @@
 // along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
 
 //! RPC interface for the ManualSeal Engine.
-use sp_consensus::ImportedAux;
+use sp_consensus::{ImportedAux, ImportResult};
 use jsonrpc_core::Error;
 use jsonrpc_derive::rpc;
 use futures::{
@@ -25,7 +25,9 @@ use futures::{
 	SinkExt
 };
 use serde::{Deserialize, Serialize};
-use sp_runtime::Justification;
+use sp_runtime::{generic::DigestItem, Justification};
+use sp_inherents::InherentData;
+use std::time::Duration;
 pub use self::gen_client::Client as ManualSealClient;
 
 /// Future's type for jsonrpc
@@ -42,15 +44,25 @@ pub enum EngineCommand<Hash> {
 	///
 	/// if finalize == true, the block will be instantly finalized.
 	SealNewBlock {
-		/// if true, empty blocks(without extrinsics) will be created.
-		/// otherwise, will return Error::EmptyTransactionPool.
-		create_empty: bool,
+		/// what to seal and how.
+		params: SealParams<Hash>,
+		/// sender to report errors/success to the rpc.
+		sender: Sender<SealOutcome<Hash>>,
+	},
+	/// Tells the engine to import a block built outside of it, e.g. to test the import path.
+	///
+	/// unlike `SealNewBlock`, the engine doesn't build anything: the header is imported as is,
+	/// digest included, and whether the block becomes the best block is decided by the configured
+	/// fork choice.
+	ImportBlock {
+		/// SCALE-encoded header of the block.
+		header: Vec<u8>,
+		/// SCALE-encoded extrinsics of the block.
+		body: Vec<Vec<u8>>,
 		/// instantly finalize this block?
 		finalize: bool,
-		/// specify the parent hash of the about-to-created block
-		parent_hash: Option<Hash>,
-		/// sender to report errors/success to the rpc.
-		sender: Sender<CreatedBlock<Hash>>,
+		/// sender to report the result of the import to.
+		sender: Sender<ImportResult>,
 	},
 	/// Tells the engine to finalize the block with the supplied hash
 	FinalizeBlock {
@@ -60,6 +72,160 @@ pub enum EngineCommand<Hash> {
 		sender: Sender<()>,
 		/// finalization justification
 		justification: Option<Justification>,
+	},
+	/// Tells the engine to finalize the blocks of the best chain from `from` to `to`, in order.
+	/// blocks that are already finalized are skipped, and finalization stops at the first failure.
+	FinalizeRange {
+		/// hash of the first block to finalize
+		from: Hash,
+		/// hash of the last block to finalize, which `from` must be an ancestor of
+		to: Hash,
+		/// sender to report errors/success to the rpc.
+		sender: Sender<()>,
+		/// finalization justification of the last block
+		justification: Option<Justification>,
+	},
+	/// Tells the engine to keep sealing blocks on the best block until the transaction
+	/// `tx_hash` is included in one of them, replying with the hash of that block.
+	/// blocks are only sealed while the pool has transactions to include.
+	SealUntilIncluded {
+		/// hash of the transaction to wait for
+		tx_hash: Hash,
+		/// maximum number of blocks to seal before giving up
+		max_blocks: u32,
+		/// sender to report the including block or errors to.
+		sender: Sender<Hash>,
+	},
+	/// Asks the engine for the current best and finalized blocks, without sealing anything.
+	QueryHead {
+		/// sender to report the chain head to.
+		sender: Sender<ChainHead<Hash>>,
+	},
+	/// Asks the engine for the blocks of the best chain from number `from` to number `to`,
+	/// included, in order.
+	ExportChain {
+		/// number of the first block to export
+		from: u64,
+		/// number of the last block to export, which must be in the best chain
+		to: u64,
+		/// sender to report the blocks to.
+		sender: Sender<Vec<ChainEntry<Hash>>>,
+	},
+	/// Advances the mock clock of the engine. if the clock hasn't been set yet, it starts from the
+	/// current system time.
+	///
+	/// once the mock clock is set, it provides the timestamp inherent of the blocks sealed without
+	/// an explicit `timestamp`. it only moves through `AdvanceTime` and `SetTime` commands.
+	AdvanceTime {
+		/// duration to advance the clock by.
+		by: Duration,
+		/// sender to report the new time (in milliseconds) to.
+		sender: Sender<u64>,
+	},
+	/// Sets the mock clock of the engine, see `AdvanceTime`.
+	SetTime {
+		/// new time, in milliseconds.
+		to: u64,
+		/// sender to report the new time (in milliseconds) to.
+		sender: Sender<u64>,
+	},
+	/// Makes the next `count` `SealNewBlock` or `SealUntilIncluded` commands fail with the error
+	/// returned by `error`, without attempting to seal anything, e.g. to test how a supervisor
+	/// handles sealing failures. sealing works normally again afterwards, and a later
+	/// `InjectFailure` replaces the failures left. errors deemed fatal by
+	/// `ManualSealConfig::is_fatal` terminate the authorship task.
+	/// for testing only: only available if the crate is built with the `test-helpers` feature.
+	#[cfg(any(test, feature = "test-helpers"))]
+	InjectFailure {
+		/// number of seal commands to fail.
+		count: u32,
+		/// creates the error each failing command fails with.
+		error: fn() -> crate::Error,
+		/// sender to report errors/success to the rpc.
+		sender: Sender<()>,
+	},
+}
+
+/// params of a `SealNewBlock` command.
+///
+/// the default params seal a block on top of the best block, failing if the transaction pool is
+/// empty, and leave everything else to the engine, so that commands only spell out what they
+/// change.
+#[derive(Clone)]
+pub struct SealParams<Hash> {
+	/// if true, empty blocks(without extrinsics) will be created.
+	/// otherwise, will return Error::EmptyTransactionPool.
+	pub create_empty: bool,
+	/// instantly finalize this block?
+	pub finalize: bool,
+	/// specify the parent hash of the about-to-created block
+	pub parent_hash: Option<Hash>,
+	/// specify the parent of the about-to-created block by its number on the best chain.
+	/// can't be used together with `parent_hash`.
+	pub parent_number: Option<u64>,
+	/// override the value of the timestamp inherent (in milliseconds) for this block.
+	pub timestamp: Option<u64>,
+	/// whether the block becomes the new best block. `None` lets the engine decide: the
+	/// longest chain wins, unless `ManualSealConfig::fork_choice` or
+	/// `ManualSealConfig::manual_best` is set.
+	pub set_best: Option<bool>,
+	/// inherent data merged into the data created by the inherent data providers.
+	/// values provided here replace the ones of the providers. The timestamp inherent is
+	/// reserved if `timestamp` is set.
+	pub extra_inherents: Option<InherentData>,
+	/// if true, no inherent data is handed to the block builder, so that the block is built
+	/// without inherents. meant for testing how the runtime handles such blocks, it can't be
+	/// used together with `timestamp` or `extra_inherents`. only available if the crate is built
+	/// with the `test-helpers` feature.
+	#[cfg(any(test, feature = "test-helpers"))]
+	pub skip_inherents: bool,
+	/// inherent data used verbatim for the block. the inherent data providers aren't
+	/// consulted, and the caller becomes responsible for providing all the inherent data the
+	/// runtime requires. it can't be used together with `timestamp`, `extra_inherents` or
+	/// `skip_inherents`.
+	pub inherent_data: Option<InherentData>,
+	/// maximum number of transaction pool extrinsics in the block. if the proposed block
+	/// includes more, it isn't imported and `Error::TooManyExtrinsics` is returned.
+	pub max_extrinsics: Option<usize>,
+	/// if the transaction pool is empty and `create_empty` is false, how long to wait for a
+	/// transaction to become ready before failing with `Error::EmptyTransactionPool`.
+	pub empty_pool_wait: Option<Duration>,
+	/// state root written into the header of the proposed block in place of the one computed
+	/// by the proposer. the block is imported with the state changes of the proposal, so
+	/// that the wrong root isn't noticed, but no block can be built or executed on top of it.
+	/// for negative tests only, e.g. of state proof verification: only available if the crate
+	/// is built with the `test-helpers` feature.
+	#[cfg(any(test, feature = "test-helpers"))]
+	pub state_root: Option<Hash>,
+	/// digest items handed to the proposer, which end up in the header of the block along
+	/// with the items added by the runtime. at most one pre-runtime item per consensus
+	/// engine is accepted.
+	pub extra_digest_items: Vec<DigestItem<Hash>>,
+	/// justification imported along with the block. only accepted if `finalize` is true,
+	/// and must not be empty.
+	pub justification: Option<Justification>,
+}
+
+impl<Hash> Default for SealParams<Hash> {
+	fn default() -> Self {
+		SealParams {
+			create_empty: false,
+			finalize: false,
+			parent_hash: None,
+			parent_number: None,
+			timestamp: None,
+			set_best: None,
+			extra_inherents: None,
+			#[cfg(any(test, feature = "test-helpers"))]
+			skip_inherents: false,
+			inherent_data: None,
+			max_extrinsics: None,
+			empty_pool_wait: None,
+			#[cfg(any(test, feature = "test-helpers"))]
+			state_root: None,
+			extra_digest_items: Vec::new(),
+			justification: None,
+		}
 	}
 }
 
@@ -67,12 +233,20 @@ pub enum EngineCommand<Hash> {
 #[rpc]
 pub trait ManualSealApi<Hash> {
 	/// Instructs the manual-seal authorship task to create a new block
+	///
+	/// if `timestamp` is provided, it replaces the value of the timestamp inherent of the block.
+	/// if `parent_number` is provided, the block is built on top of the block with this number
+	/// on the best chain.
+	/// if `set_best` is provided, it decides whether the block becomes the new best block.
 	#[rpc(name = "engine_createBlock")]
 	fn create_block(
 		&self,
 		create_empty: bool,
 		finalize: bool,
-		parent_hash: Option<Hash>
+		parent_hash: Option<Hash>,
+		timestamp: Option<u64>,
+		parent_number: Option<u64>,
+		set_best: Option<bool>,
 	) -> FutureResult<CreatedBlock<Hash>>;
 
 	/// Instructs the manual-seal authorship task to finalize a block
@@ -95,7 +269,73 @@ pub struct CreatedBlock<Hash> {
 	/// hash of the created block.
 	pub hash: Hash,
 	/// some extra details about the import operation
-	pub aux: ImportedAux
+	pub aux: ImportedAux,
+	/// hashes of the inherent extrinsics included in the block.
+	pub inherents: Vec<Hash>,
+	/// hashes of the transaction pool extrinsics included in the block.
+	pub transactions: Vec<Hash>,
+	/// size of the SCALE-encoded block, in bytes.
+	pub size: usize,
+	/// how the transactions ready in the pool fared when the block was proposed.
+	pub selection: TransactionSelection,
+}
+
+/// counts of the transactions ready in the pool when a block was proposed, as reported in
+/// `CreatedBlock::selection`
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct TransactionSelection {
+	/// number of transactions ready in the pool when the proposer started.
+	pub considered: usize,
+	/// number of transactions from the pool included in the block.
+	pub included: usize,
+	/// number of ready transactions left out of the block, e.g. because the block was full, they
+	/// turned out invalid or the proposal deadline was reached. the proposer doesn't report why
+	/// each of them was skipped.
+	pub skipped: usize,
+}
+
+/// best and finalized blocks, as reported by `EngineCommand::QueryHead`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainHead<Hash> {
+	/// hash of the best block.
+	pub best_hash: Hash,
+	/// number of the best block.
+	pub best_number: u64,
+	/// hash of the last finalized block.
+	pub finalized_hash: Hash,
+	/// number of the last finalized block.
+	pub finalized_number: u64,
+}
+
+/// block of the best chain, as reported by `EngineCommand::ExportChain`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainEntry<Hash> {
+	/// hash of the block.
+	pub hash: Hash,
+	/// number of the block.
+	pub number: u64,
+	/// whether the block is finalized.
+	pub finalized: bool,
+}
+
+/// outcome of a `SealNewBlock` command
+#[derive(Debug, PartialEq, Eq)]
+pub enum SealOutcome<Hash> {
+	/// a new block has been sealed and imported.
+	Sealed(CreatedBlock<Hash>),
+	/// the sealed block is identical to a block that is already in the chain, e.g. because it
+	/// was built on the same parent with the same content. nothing has been imported.
+	AlreadyInChain(Hash),
+}
+
+impl<Hash> SealOutcome<Hash> {
+	/// returns the new block, if one has been sealed.
+	pub fn created_block(self) -> Option<CreatedBlock<Hash>> {
+		match self {
+			SealOutcome::Sealed(created_block) => Some(created_block),
+			SealOutcome::AlreadyInChain(_) => None,
+		}
+	}
 }
 
 impl<Hash> ManualSeal<Hash> {
@@ -110,19 +350,33 @@ impl<Hash: Send + 'static> ManualSealApi<Hash> for ManualSeal<Hash> {
 		&self,
 		create_empty: bool,
 		finalize: bool,
-		parent_hash: Option<Hash>
+		parent_hash: Option<Hash>,
+		timestamp: Option<u64>,
+		parent_number: Option<u64>,
+		set_best: Option<bool>,
 	) -> FutureResult<CreatedBlock<Hash>> {
 		let mut sink = self.import_block_channel.clone();
 		let future = async move {
 			let (sender, receiver) = oneshot::channel();
 			let command = EngineCommand::SealNewBlock {
-				create_empty,
-				finalize,
-				parent_hash,
+				params: SealParams {
+					create_empty,
+					finalize,
+					parent_hash,
+					parent_number,
+					timestamp,
+					set_best,
+					..Default::default()
+				},
 				sender: Some(sender),
 			};
 			sink.send(command).await?;
-			receiver.await?
+			match receiver.await?? {
+				SealOutcome::Sealed(created_block) => Ok(created_block),
+				// `engine_createBlock` only reports new blocks.
+				SealOutcome::AlreadyInChain(_) =>
+					Err(crate::Error::BlockImportError(ImportResult::AlreadyInChain)),
+			}
 		}.boxed();
 
 		Box::new(future.map_err(Error::from).compat())
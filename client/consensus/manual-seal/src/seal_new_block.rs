@@ -16,41 +16,87 @@
 
 //! Block sealing utilities
 
-use crate::{Error, rpc};
-use std::sync::Arc;
+use crate::{Error, ManualSealConfig, rpc};
+use std::{cmp::Ordering, convert::TryFrom, sync::Arc};
 use sp_runtime::{
-	traits::{Block as BlockT, Header as HeaderT},
-	generic::BlockId,
+	traits::{Block as BlockT, Header as HeaderT, NumberFor, Zero},
+	generic::{BlockId, Digest, DigestItem},
 };
 use futures::prelude::*;
+use codec::Encode;
 use sc_transaction_pool::txpool;
-use rpc::CreatedBlock;
+use rpc::{CreatedBlock, SealOutcome, SealParams, TransactionSelection};
 
 use sp_consensus::{
 	self, BlockImport, Environment, Proposer,
 	ForkChoiceStrategy, BlockImportParams, BlockOrigin,
-	ImportResult, SelectChain,
+	ImportResult, SelectChain, Error as ConsensusError,
 	import_queue::BoxBlockImport,
 };
+use sp_api::ProvideRuntimeApi;
+use sp_block_builder::BlockBuilder as BlockBuilderApi;
 use sp_blockchain::HeaderBackend;
-use std::collections::HashMap;
-use std::time::Duration;
-use sp_inherents::InherentDataProviders;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use sp_inherents::{InherentData, InherentDataProviders};
 
 /// max duration for creating a proposal in secs
 const MAX_PROPOSAL_DURATION: u64 = 10;
 
+/// phase of sealing a block, see `ManualSealConfig::timings`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SealPhase {
+	/// creation of the inherent data.
+	Inherents,
+	/// creation of the proposer and proposal of the block.
+	Proposal,
+	/// import of the block, including retries.
+	Import,
+}
+
+/// time spent in each phase of sealing a block, in the order of the phases.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SealTimings {
+	/// phases and their durations.
+	pub phases: Vec<(SealPhase, Duration)>,
+}
+
+/// measures the phases of sealing a block, if enabled.
+struct PhaseRecorder(Option<(Instant, Vec<(SealPhase, Duration)>)>);
+
+impl PhaseRecorder {
+	fn new(enabled: bool) -> Self {
+		PhaseRecorder(if enabled { Some((Instant::now(), Vec::new())) } else { None })
+	}
+
+	/// starts measuring a phase.
+	fn start(&mut self) {
+		if let Some((start, _)) = &mut self.0 {
+			*start = Instant::now();
+		}
+	}
+
+	/// records the phase started by the last call to `start`.
+	fn finish(&mut self, phase: SealPhase) {
+		if let Some((start, phases)) = &mut self.0 {
+			phases.push((phase, start.elapsed()));
+		}
+	}
+
+	fn into_timings(self) -> Option<SealTimings> {
+		self.0.map(|(_, phases)| SealTimings { phases })
+	}
+}
+
 /// params for sealing a new block
 pub struct SealBlockParams<'a, B: BlockT, SC, HB, E, T, P: txpool::ChainApi> {
-	/// if true, empty blocks(without extrinsics) will be created.
-	/// otherwise, will return Error::EmptyTransactionPool.
-	pub create_empty: bool,
-	/// instantly finalize this block?
-	pub finalize: bool,
-	/// specify the parent hash of the about-to-created block
-	pub parent_hash: Option<<B as BlockT>::Hash>,
+	/// what to seal and how, see `EngineCommand::SealNewBlock`
+	pub params: SealParams<<B as BlockT>::Hash>,
+	/// number of blocks to go back from the best block to find the parent, if the parent isn't
+	/// specified
+	pub fork_depth: Option<u32>,
 	/// sender to report errors/success to the rpc.
-	pub sender: rpc::Sender<CreatedBlock<<B as BlockT>::Hash>>,
+	pub sender: rpc::Sender<SealOutcome<<B as BlockT>::Hash>>,
 	/// transaction pool
 	pub pool: Arc<txpool::Pool<P>>,
 	/// header backend
@@ -63,75 +109,380 @@ pub struct SealBlockParams<'a, B: BlockT, SC, HB, E, T, P: txpool::ChainApi> {
 	pub block_import: &'a mut BoxBlockImport<B, T>,
 	/// inherent data provider
 	pub inherent_data_provider: &'a InherentDataProviders,
+	/// configuration of the authorship task
+	pub config: &'a ManualSealConfig<<B as BlockT>::Header>,
+}
+
+/// walks `depth` blocks back from `header`, stopping at the genesis block.
+fn ancestor<B, HB>(
+	client: &HB,
+	mut header: <B as BlockT>::Header,
+	depth: u32,
+) -> Result<<B as BlockT>::Header, Error>
+	where
+		B: BlockT,
+		HB: HeaderBackend<B>,
+{
+	for _ in 0..depth {
+		if header.number().is_zero() {
+			break
+		}
+		let parent_hash = *header.parent_hash();
+		header = match client.header(BlockId::Hash(parent_hash))? {
+			Some(parent) => parent,
+			None => return Err(Error::BlockNotFound(format!("{}", parent_hash))),
+		};
+	}
+
+	Ok(header)
+}
+
+/// decides whether `header` becomes the best block once imported: as requested by `set_best`,
+/// or else as configured by `ManualSealConfig::fork_choice` or `ManualSealConfig::manual_best`,
+/// or else by the longest chain rule.
+pub(crate) fn fork_choice<B, HB>(
+	client: &HB,
+	config: &ManualSealConfig<<B as BlockT>::Header>,
+	set_best: Option<bool>,
+	header: &<B as BlockT>::Header,
+) -> Result<ForkChoiceStrategy, Error>
+	where
+		B: BlockT,
+		HB: HeaderBackend<B>,
+{
+	Ok(match (set_best, config.fork_choice) {
+		(Some(set_best), _) => ForkChoiceStrategy::Custom(set_best),
+		(None, Some(compare)) => {
+			let best_hash = client.info().best_hash;
+			let best = match client.header(BlockId::Hash(best_hash))? {
+				Some(best) => best,
+				None => return Err(Error::BlockNotFound(format!("{}", best_hash))),
+			};
+			ForkChoiceStrategy::Custom(compare(header, &best) == Ordering::Greater)
+		},
+		(None, None) if config.manual_best => ForkChoiceStrategy::Custom(false),
+		(None, None) => ForkChoiceStrategy::LongestChain,
+	})
 }
 
-/// seals a new block with the given params
+/// seals a new block with the given params, returns true if a new block has been sealed.
+///
+/// fails only if sealing failed with an error that `ManualSealConfig::is_fatal` deems fatal, in
+/// which case the error isn't sent to the rpc.
 pub async fn seal_new_block<B, SC, HB, E, T, P>(
 	SealBlockParams {
-		create_empty,
-		finalize,
+		params: SealParams {
+			create_empty,
+			finalize,
+			parent_hash,
+			parent_number,
+			timestamp,
+			set_best,
+			extra_inherents,
+			#[cfg(any(test, feature = "test-helpers"))]
+			skip_inherents,
+			inherent_data,
+			max_extrinsics,
+			empty_pool_wait,
+			#[cfg(any(test, feature = "test-helpers"))]
+			state_root,
+			extra_digest_items,
+			justification,
+		},
 		pool,
-		parent_hash,
+		fork_depth,
 		client,
 		select_chain,
 		block_import,
 		env,
 		inherent_data_provider,
+		config,
 		mut sender,
 		..
 	}: SealBlockParams<'_, B, SC, HB, E, T, P>
-)
+) -> Result<bool, Error>
 	where
 		B: BlockT,
-		HB: HeaderBackend<B>,
+		HB: HeaderBackend<B> + ProvideRuntimeApi<B>,
+		HB::Api: BlockBuilderApi<B, Error = sp_blockchain::Error>,
 		E: Environment<B>,
+		E::Proposer: Proposer<B, Transaction = T>,
 		<E as Environment<B>>::Error: std::fmt::Display,
 		<E::Proposer as Proposer<B>>::Error: std::fmt::Display,
 		P: txpool::ChainApi<Block=B>,
 		SC: SelectChain<B>,
 {
 	let future = async {
+		match &justification {
+			Some(_) if !finalize => return Err(Error::StringError(
+				"a justification can only be supplied for a finalized block".into()
+			)),
+			Some(justification) if justification.is_empty() => return Err(Error::StringError(
+				"the justification is empty".into()
+			)),
+			_ => {}
+		}
+
 		if pool.validated_pool().status().ready == 0 && !create_empty {
-			return Err(Error::EmptyTransactionPool)
+			let ready = match empty_pool_wait {
+				Some(wait) => wait_for_ready_transaction(&pool, wait).await,
+				None => false,
+			};
+			if !ready {
+				return Err(Error::EmptyTransactionPool)
+			}
 		}
 
 		// get the header to build this new block on.
-		// use the parent_hash supplied via `EngineCommand`
+		// use the parent_hash or parent_number supplied via `EngineCommand`
 		// or fetch the best_block.
-		let header = match parent_hash {
-			Some(hash) => {
+		let header = match (parent_hash, parent_number) {
+			(Some(_), Some(_)) => return Err(Error::StringError(
+				"parent_hash and parent_number can't be supplied together".into()
+			)),
+			(Some(hash), None) => {
 				match client.header(BlockId::Hash(hash))? {
 					Some(header) => header,
 					None => return Err(Error::BlockNotFound(format!("{}", hash))),
 				}
 			}
-			None => select_chain.best_chain()?
+			(None, Some(number)) => {
+				let header = match NumberFor::<B>::try_from(number) {
+					Ok(n) => client.header(BlockId::Number(n))?,
+					Err(_) => None,
+				};
+				match header {
+					Some(header) => header,
+					None => return Err(Error::BlockNumberNotFound(number)),
+				}
+			}
+			(None, None) => {
+				let best = select_chain.best_chain()?;
+				match fork_depth {
+					Some(depth) => ancestor::<B, _>(&*client, best, depth)?,
+					None => best,
+				}
+			}
 		};
 
+		if let Some(predicate) = &config.seal_predicate {
+			if !predicate.allows(&header) {
+				let hash = <B as BlockT>::Header::hash(&header);
+				return Err(Error::SealRefused(format!("{}", hash)))
+			}
+		}
+
+		let mut recorder = PhaseRecorder::new(config.timings.is_some());
+		recorder.start();
+		#[cfg(not(any(test, feature = "test-helpers")))]
+		let skip_inherents = false;
+		let verbatim = match (inherent_data, skip_inherents) {
+			(Some(_), true) => return Err(Error::StringError(
+				"inherent data can't be supplied when skipping inherents".into()
+			)),
+			(Some(inherent_data), false) => Some(inherent_data),
+			(None, true) => Some(InherentData::new()),
+			(None, false) => None,
+		};
+		let id = match verbatim {
+			Some(_) if timestamp.is_some() || extra_inherents.is_some() => {
+				return Err(Error::StringError(
+					"timestamp and extra inherents can't be supplied with verbatim inherent data".into()
+				))
+			},
+			Some(inherent_data) => inherent_data,
+			None => create_inherent_data(inherent_data_provider, extra_inherents, timestamp)?,
+		};
+		let inherents_len = id.len();
+		let check_data = if config.validate_inherents { Some(id.clone()) } else { None };
+		recorder.finish(SealPhase::Inherents);
+
+		// a block can't carry several pre-runtime digests of the same engine.
+		let mut pre_runtime_engines = HashSet::new();
+		for item in &extra_digest_items {
+			if let DigestItem::PreRuntime(engine_id, _) = item {
+				if !pre_runtime_engines.insert(*engine_id) {
+					return Err(Error::StringError(format!(
+						"conflicting pre-runtime digest items for engine {:?}", engine_id
+					)))
+				}
+			}
+		}
+		let digest = Digest { logs: extra_digest_items };
+
+		recorder.start();
+		// the proposer doesn't report the transactions it skips, so they are inferred from the
+		// transactions ready when it starts.
+		let considered = pool.validated_pool().status().ready;
 		let proposer = env.init(&header)
 			.map_err(|err| Error::StringError(format!("{}", err))).await?;
-		let id = inherent_data_provider.create_inherent_data()?;
-		let inherents_len = id.len();
-		let proposal = proposer.propose(id, Default::default(), Duration::from_secs(MAX_PROPOSAL_DURATION), false.into())
+		let proposal = proposer.propose(id, digest, Duration::from_secs(MAX_PROPOSAL_DURATION), false.into())
 			.map_err(|err| Error::StringError(format!("{}", err))).await?;
+		recorder.finish(SealPhase::Proposal);
+
+		// a misconfigured environment could build on another block than the one it was given.
+		let parent_hash = <B as BlockT>::Header::hash(&header);
+		let proposed_parent_hash = *proposal.block.header().parent_hash();
+		if proposed_parent_hash != parent_hash {
+			return Err(Error::StringError(format!(
+				"the proposed block is built on {} instead of {}", proposed_parent_hash, parent_hash
+			)))
+		}
 
 		if proposal.block.extrinsics().len() == inherents_len && !create_empty {
 			return Err(Error::EmptyTransactionPool)
 		}
 
+		if let Some(check_data) = check_data {
+			let parent_id = BlockId::Hash(parent_hash);
+			let result = client.runtime_api()
+				.check_inherents(&parent_id, proposal.block.clone(), check_data)?;
+			if !result.ok() {
+				let errors = result.into_errors()
+					.map(|(id, err)| inherent_data_provider.error_to_string(&id, &err))
+					.collect::<Vec<_>>();
+				return Err(Error::InvalidInherents(errors.join(", ")))
+			}
+		}
+
+		// extrinsics that didn't come from the pool are inherents.
+		let (inherents, transactions): (Vec<_>, Vec<_>) = proposal.block.extrinsics()
+			.iter()
+			.map(|xt| pool.hash_of(xt))
+			.partition(|hash| pool.validated_pool().ready_by_hash(hash).is_none());
+
+		if let Some(max_extrinsics) = max_extrinsics {
+			if transactions.len() > max_extrinsics {
+				return Err(Error::TooManyExtrinsics(transactions.len(), max_extrinsics))
+			}
+		}
+
+		// overriding the state root doesn't change the size of the block.
+		let size = proposal.block.encoded_size();
 		let (header, body) = proposal.block.deconstruct();
-		let mut params = BlockImportParams::new(BlockOrigin::Own, header.clone());
-		params.body = Some(body);
-		params.finalized = finalize;
-		params.fork_choice = Some(ForkChoiceStrategy::LongestChain);
-
-		match block_import.import_block(params, HashMap::new())? {
-			ImportResult::Imported(aux) => {
-				Ok(CreatedBlock { hash: <B as BlockT>::Header::hash(&header), aux })
+		let mut storage_changes = None;
+		// the block can't be executed with a wrong state root, so it is imported with the state
+		// changes of the proposal instead.
+		#[cfg(any(test, feature = "test-helpers"))]
+		let header = match state_root {
+			Some(state_root) => {
+				let mut header = header;
+				header.set_state_root(state_root);
+				storage_changes = Some(proposal.storage_changes);
+				header
 			},
-			other => Err(other.into()),
+			None => header,
+		};
+		let fork_choice = fork_choice::<B, _>(&*client, config, set_best, &header)?;
+
+		// a retry executes the block, so it fails if the state root has been overridden.
+		let retriable = storage_changes.is_none();
+		recorder.start();
+		let mut retries = 0;
+		loop {
+			let mut params = BlockImportParams::new(BlockOrigin::Own, header.clone());
+			params.body = Some(body.clone());
+			params.finalized = finalize;
+			params.justification = justification.clone();
+			params.fork_choice = Some(fork_choice);
+			params.storage_changes = storage_changes.take();
+
+			match block_import.import_block(params, HashMap::new()) {
+				Ok(ImportResult::Imported(aux)) => {
+					recorder.finish(SealPhase::Import);
+					if let (Some(sink), Some(timings)) = (&config.timings, recorder.into_timings()) {
+						let _ = sink.unbounded_send(timings);
+					}
+					return Ok(SealOutcome::Sealed(CreatedBlock {
+						hash: <B as BlockT>::Header::hash(&header),
+						aux,
+						inherents,
+						selection: TransactionSelection {
+							considered,
+							included: transactions.len(),
+							skipped: considered.saturating_sub(transactions.len()),
+						},
+						transactions,
+						size,
+					}))
+				},
+				Ok(ImportResult::AlreadyInChain) => {
+					return Ok(SealOutcome::AlreadyInChain(<B as BlockT>::Header::hash(&header)))
+				},
+				Ok(other) => return Err(other.into()),
+				Err(err) if retriable &&
+					(config.is_transient)(&err) &&
+					retries < config.max_import_retries =>
+				{
+					retries += 1;
+					log::warn!(
+						"Failed to import sealed block, retrying ({}/{}): {}",
+						retries, config.max_import_retries, err,
+					);
+				},
+				Err(err) => return Err(err.into()),
+			}
+
+			futures_timer::Delay::new(config.import_retry_backoff).await;
 		}
 	};
 
-	rpc::send_result(&mut sender, future.await)
+	match future.await {
+		Err(err) if (config.is_fatal)(&err) => Err(err),
+		result => {
+			let sealed = matches!(result, Ok(SealOutcome::Sealed(_)));
+			rpc::send_result(&mut sender, result);
+			Ok(sealed)
+		},
+	}
+}
+
+/// waits up to `wait` for a transaction to become ready in the pool, returns false on timeout.
+async fn wait_for_ready_transaction<P: txpool::ChainApi>(
+	pool: &txpool::Pool<P>,
+	wait: Duration,
+) -> bool {
+	let mut imported = pool.validated_pool().import_notification_stream();
+	// a transaction may have become ready before we subscribed.
+	if pool.validated_pool().status().ready > 0 {
+		return true
+	}
+
+	match future::select(imported.next(), futures_timer::Delay::new(wait)).await {
+		future::Either::Left((imported, _)) => imported.is_some(),
+		future::Either::Right(_) => false,
+	}
+}
+
+/// creates the inherent data for a new block.
+///
+/// `extra_inherents` take precedence over the data of the providers, and `timestamp` takes
+/// precedence over both.
+pub(crate) fn create_inherent_data(
+	inherent_data_provider: &InherentDataProviders,
+	extra_inherents: Option<InherentData>,
+	timestamp: Option<u64>,
+) -> Result<InherentData, Error> {
+	let mut inherent_data = inherent_data_provider.create_inherent_data()?;
+	if let Some(extra_inherents) = extra_inherents {
+		inherent_data.merge(extra_inherents);
+	}
+	if let Some(timestamp) = timestamp {
+		inherent_data.replace_data::<sp_timestamp::InherentType>(
+			sp_timestamp::INHERENT_IDENTIFIER,
+			&timestamp,
+		);
+	}
+
+	Ok(inherent_data)
+}
+
+/// default `ManualSealConfig::is_transient`: returns true if a block import that failed with
+/// this error may succeed if tried again.
+pub(crate) fn is_transient(err: &ConsensusError) -> bool {
+	match err {
+		ConsensusError::StateUnavailable(_) |
+		ConsensusError::IoTerminated => true,
+		_ => false,
+	}
 }
@@ -0,0 +1,248 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2020 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Manual seal test helpers. Utilities for running the engine on top of an in-memory
+//! test client, so that tests only have to send `EngineCommand`s.
+
+use crate::{
+	run_manual_seal, ChainHead, CreatedBlock, EngineCommand, Error, ManualSealConfig, SealOutcome,
+	SealParams,
+};
+use futures::{prelude::*, channel::{mpsc, oneshot}};
+use sc_basic_authorship::ProposerFactory;
+use sc_client_api::backend::TransactionFor;
+use sc_transaction_pool::{BasicPool, RevalidationType, txpool::Options};
+use sp_blockchain::HeaderBackend;
+use sp_consensus::import_queue::BoxBlockImport;
+use sp_inherents::InherentDataProviders;
+use sp_runtime::generic::BlockId;
+use sp_transaction_pool::{TransactionPool, TransactionSource};
+use std::sync::Arc;
+use substrate_test_runtime_client::{
+	Backend, DefaultTestClientBuilderExt, LongestChain, TestClientBuilderExt, TestClientBuilder,
+	TestClient, runtime::{Block, Extrinsic, Hash, Header},
+};
+use substrate_test_runtime_transaction_pool::TestApi;
+
+/// Transaction pool used by the [`TestEngine`].
+pub type TestPool = BasicPool<TestApi, Block>;
+
+/// A manual seal engine running in the background on top of an in-memory test client.
+pub struct TestEngine {
+	/// Client the engine imports its blocks into.
+	pub client: Arc<TestClient>,
+	/// Transaction pool the engine takes its transactions from.
+	pub pool: Arc<TestPool>,
+	/// Chain api of the transaction pool, used to tell the pool about new blocks.
+	pub pool_api: Arc<TestApi>,
+	/// Sends commands to the engine.
+	pub commands: mpsc::Sender<EngineCommand<Hash>>,
+}
+
+impl TestEngine {
+	/// Seals a new block as described by `params` and waits for the result.
+	pub async fn seal(&self, params: SealParams<Hash>) -> Result<SealOutcome<Hash>, Error> {
+		let (sender, receiver) = oneshot::channel();
+		self.commands.clone().send(EngineCommand::SealNewBlock {
+			params,
+			sender: Some(sender),
+		}).await?;
+
+		receiver.await?
+	}
+
+	/// Seals a new block on top of the best block and waits for the result.
+	pub async fn seal_block(
+		&mut self,
+		create_empty: bool,
+		finalize: bool,
+	) -> Result<SealOutcome<Hash>, Error> {
+		self.seal(SealParams { create_empty, finalize, ..Default::default() }).await
+	}
+
+	/// Submits `extrinsic` to the pool and seals a block including it.
+	///
+	/// Fails without sealing anything if the extrinsic is invalid or isn't ready to be included,
+	/// e.g. because of a nonce gap. Otherwise, returns the hash of the extrinsic along with the
+	/// sealed block, which is guaranteed to include it. As the pool validates the extrinsic at the
+	/// best block, the pool api must know about that block.
+	pub async fn submit_and_seal(
+		&mut self,
+		extrinsic: Extrinsic,
+		finalize: bool,
+	) -> Result<(Hash, CreatedBlock<Hash>), Error> {
+		let at = BlockId::Number(self.client.info().best_number);
+		let hash = self.pool.submit_one(&at, TransactionSource::External, extrinsic).await
+			.map_err(|err| Error::Other(Box::new(err)))?;
+		if self.pool.ready_transaction(&hash).is_none() {
+			return Err(Error::StringError(format!("Transaction {:?} is not ready", hash)))
+		}
+
+		let created_block = match self.seal_block(false, finalize).await? {
+			SealOutcome::Sealed(created_block) => created_block,
+			SealOutcome::AlreadyInChain(block) => return Err(Error::StringError(
+				format!("Block {:?} including transaction {:?} is already in chain", block, hash)
+			)),
+		};
+		if !created_block.transactions.contains(&hash) {
+			return Err(Error::StringError(
+				format!("Transaction {:?} hasn't been included in block {:?}", hash, created_block.hash)
+			))
+		}
+
+		Ok((hash, created_block))
+	}
+
+	/// Returns the best and finalized blocks known to the engine.
+	pub async fn query_head(&mut self) -> Result<ChainHead<Hash>, Error> {
+		let (sender, receiver) = oneshot::channel();
+		self.commands.send(EngineCommand::QueryHead { sender: Some(sender) }).await?;
+
+		receiver.await?
+	}
+}
+
+/// A fresh in-memory chain: client, select chain and transaction pool, for tests that need to
+/// customize how the engine is run on top of it.
+pub struct TestChain {
+	/// Client of the chain.
+	pub client: Arc<TestClient>,
+	/// Select chain of the client.
+	pub select_chain: LongestChain<Backend, Block>,
+	/// Transaction pool of the chain.
+	pub pool: Arc<TestPool>,
+	/// Chain api of the transaction pool.
+	pub pool_api: Arc<TestApi>,
+}
+
+impl TestChain {
+	/// Creates a chain that only contains the genesis block, with an empty pool.
+	pub fn new() -> Self {
+		let (client, select_chain) = TestClientBuilder::new().build_with_longest_chain();
+		let pool_api = Arc::new(TestApi::empty());
+		let spawner = sp_core::testing::TaskExecutor::new();
+		let pool = Arc::new(BasicPool::with_revalidation_type(
+			Options::default(), pool_api.clone(), None, RevalidationType::Full, spawner,
+		));
+
+		TestChain {
+			client: Arc::new(client),
+			select_chain,
+			pool,
+			pool_api,
+		}
+	}
+
+	/// Proposer factory building blocks out of the transactions of the pool.
+	pub fn proposer_factory(&self) -> ProposerFactory<TestPool, Backend, TestClient> {
+		ProposerFactory::new(self.client.clone(), self.pool.clone(), None)
+	}
+
+	/// Starts the manual seal engine on a background thread, with the given inherent data
+	/// providers.
+	pub fn start(
+		self,
+		inherent_data_providers: InherentDataProviders,
+		config: ManualSealConfig<Header>,
+	) -> TestEngine {
+		let block_import = Box::new(self.client.clone());
+		self.start_with_block_import(block_import, inherent_data_providers, config)
+	}
+
+	/// Starts the manual seal engine on a background thread, importing its blocks through
+	/// `block_import` rather than directly into the client.
+	pub fn start_with_block_import(
+		self,
+		block_import: BoxBlockImport<Block, TransactionFor<Backend, Block>>,
+		inherent_data_providers: InherentDataProviders,
+		config: ManualSealConfig<Header>,
+	) -> TestEngine {
+		let (commands, stream) = mpsc::channel(1024);
+		let future = run_manual_seal(
+			block_import,
+			self.proposer_factory(),
+			self.client.clone(),
+			self.pool.pool().clone(),
+			stream,
+			self.select_chain,
+			inherent_data_providers,
+			config,
+		);
+		std::thread::spawn(move || futures::executor::block_on(future));
+
+		TestEngine {
+			client: self.client,
+			pool: self.pool,
+			pool_api: self.pool_api,
+			commands,
+		}
+	}
+}
+
+impl Default for TestChain {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Starts the manual seal engine on a background thread, on top of a fresh in-memory client,
+/// select chain and transaction pool.
+pub fn start_test_engine(config: ManualSealConfig<Header>) -> TestEngine {
+	TestChain::new().start(InherentDataProviders::new(), config)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use substrate_test_runtime_client::AccountKeyring::Alice;
+	use substrate_test_runtime_transaction_pool::uxt;
+
+	#[test]
+	fn seals_blocks_on_test_engine() {
+		let mut engine = start_test_engine(Default::default());
+
+		futures::executor::block_on(async {
+			engine.seal_block(true, false).await.unwrap();
+			engine.seal_block(true, false).await.unwrap();
+		});
+
+		assert_eq!(engine.client.info().best_number, 2);
+	}
+
+	#[test]
+	fn submits_and_seals_transactions() {
+		let mut engine = start_test_engine(Default::default());
+		let valid = uxt(Alice, 0);
+		let invalid = uxt(Alice, 1);
+		engine.pool_api.add_invalid(&invalid);
+
+		futures::executor::block_on(async {
+			// the invalid transaction doesn't lead to a block.
+			assert_matches::assert_matches!(
+				engine.submit_and_seal(invalid, false).await,
+				Err(Error::Other(_))
+			);
+			assert_eq!(engine.client.info().best_number, 0);
+
+			let (hash, created_block) = engine.submit_and_seal(valid, false).await.unwrap();
+			assert_eq!(created_block.transactions, vec![hash]);
+		});
+
+		assert_eq!(engine.client.info().best_number, 1);
+	}
+}
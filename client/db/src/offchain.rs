@@ -28,6 +28,9 @@ use parking_lot::Mutex;
 use log::error;
 
 /// Offchain local storage
+///
+/// Every write is committed to the database right away, so values set before the node shuts
+/// down are found again once the database is reopened.
 #[derive(Clone)]
 pub struct LocalStorage {
 	db: Arc<dyn Database<DbHash>>,
@@ -85,6 +88,11 @@ impl sp_core::offchain::OffchainStorage for LocalStorage {
 		self.db.get(columns::OFFCHAIN, &key)
 	}
 
+	fn len(&self, prefix: &[u8], key: &[u8]) -> Option<usize> {
+		let key: Vec<u8> = prefix.iter().chain(key).cloned().collect();
+		sp_database::with_get(&*self.db, columns::OFFCHAIN, &key, |value| value.len())
+	}
+
 	fn compare_and_set(
 		&mut self,
 		prefix: &[u8],
@@ -153,4 +161,39 @@ mod tests {
 		assert!(storage.locks.lock().is_empty(), "Locks map should be empty!");
 	}
 
+	#[test]
+	fn should_get_the_length_of_values() {
+		let mut storage = LocalStorage::new_test();
+		let prefix = b"prefix";
+		let key = b"key";
+
+		assert_eq!(storage.len(prefix, key), None);
+		storage.set(prefix, key, &[0; 1000]);
+		assert_eq!(storage.len(prefix, key), Some(1000));
+	}
+
+	#[test]
+	fn should_keep_values_across_a_reopen() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().to_str().unwrap();
+		let open = || {
+			let config = kvdb_rocksdb::DatabaseConfig::with_columns(crate::utils::NUM_COLUMNS);
+			let db = kvdb_rocksdb::Database::open(&config, path).unwrap();
+			LocalStorage::new(sp_database::as_database(db))
+		};
+		let prefix = b"prefix";
+
+		{
+			let mut storage = open();
+			storage.set(prefix, b"set", b"value");
+			storage.set(prefix, b"removed", b"value");
+			storage.remove(prefix, b"removed");
+			assert!(storage.compare_and_set(prefix, b"swapped", None, b"value"));
+		}
+
+		let storage = open();
+		assert_eq!(storage.get(prefix, b"set"), Some(b"value".to_vec()));
+		assert_eq!(storage.get(prefix, b"removed"), None);
+		assert_eq!(storage.get(prefix, b"swapped"), Some(b"value".to_vec()));
+	}
 }
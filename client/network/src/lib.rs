@@ -292,8 +292,14 @@ pub trait NetworkStateInfo {
 	/// Returns the local external addresses.
 	fn external_addresses(&self) -> Vec<Multiaddr>;
 
+	/// Returns the addresses the local node is listening on.
+	fn listen_addresses(&self) -> Vec<Multiaddr>;
+
 	/// Returns the local Peer ID.
 	fn local_peer_id(&self) -> PeerId;
+
+	/// Returns the number of peers the local node is connected to.
+	fn num_connected_peers(&self) -> usize;
 }
 
 /// Overview status of the network.
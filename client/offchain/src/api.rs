@@ -15,23 +15,30 @@
 // along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::{
+	collections::HashMap,
+	fmt,
+	net::IpAddr,
 	str::FromStr,
-	sync::Arc,
+	sync::{Arc, atomic::{AtomicU64, Ordering}},
 	convert::TryFrom,
-	thread::sleep,
+	path::PathBuf,
+	pin::Pin,
+	time::{Duration, Instant},
 };
 
 use sp_core::offchain::OffchainStorage;
-use futures::Future;
-use log::error;
+use futures::{channel::oneshot, future, Future, FutureExt};
+use lazy_static::lazy_static;
+use log::{debug, error, warn};
+use parking_lot::Mutex;
 use sc_network::{PeerId, Multiaddr, NetworkStateInfo};
 use codec::{Encode, Decode};
 use sp_core::offchain::{
-	Externalities as OffchainExt, HttpRequestId, Timestamp, HttpRequestStatus, HttpError,
-	OpaqueNetworkState, OpaquePeerId, OpaqueMultiaddr, StorageKind,
+	Externalities as OffchainExt, DnsRecordType, HttpRequestId, Timestamp, HttpRequestStatus,
+	HttpError, HttpStats, OpaqueNetworkState, OpaquePeerId, OpaqueMultiaddr, StorageKind, NodeRole,
 };
 pub use sp_offchain::STORAGE_PREFIX;
-pub use http::SharedClient;
+pub use http::{HttpWorkerHealth, SharedClient};
 
 #[cfg(not(target_os = "unknown"))]
 mod http;
@@ -41,8 +48,580 @@ use http_dummy as http;
 #[cfg(target_os = "unknown")]
 mod http_dummy;
 
+mod compression;
 mod timestamp;
 
+/// Default maximum duration an offchain worker can sleep for in a single call to `sleep_until`.
+const DEFAULT_MAX_SLEEP: Duration = Duration::from_secs(5 * 60);
+
+/// Default number of redirects followed for a single HTTP request.
+const DEFAULT_MAX_REDIRECTS: usize = 5;
+
+/// Configuration of the HTTP capabilities of the offchain API.
+#[derive(Clone, Debug)]
+pub struct HttpConfig {
+	/// If `true`, the HTTP worker transparently follows `3xx` responses carrying a `Location`
+	/// header. If `false`, the redirect response is handed to the offchain worker as is.
+	pub follow_redirects: bool,
+	/// Maximum number of redirects followed for a single request. Once the limit is reached, the
+	/// last redirect response is handed to the offchain worker as is.
+	pub max_redirects: usize,
+	/// If set, HTTP interactions are recorded to or replayed from a cassette file.
+	pub recording: Option<HttpRecording>,
+	/// Maximum number of responses to `GET` requests kept to make conditional requests. Later
+	/// requests to the same URI are sent with `If-None-Match` or `If-Modified-Since`, and a
+	/// `304 Not Modified` response is transparently replaced with the cached one. `0` disables
+	/// the cache.
+	pub cache_size: usize,
+	/// Oldest TLS version accepted for `https://` requests. Handshakes negotiating an older
+	/// version fail.
+	pub min_tls_version: TlsVersion,
+	/// Versions of HTTP used for the requests. Requests to servers that don't speak any of them
+	/// fail with an I/O error.
+	pub protocol: HttpProtocol,
+	/// Proxy the HTTP requests go through, if any. Defaults to the proxy configured through the
+	/// `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` environment variables.
+	pub proxy: Option<HttpProxy>,
+	/// Resolver of the host names of the requests. Uses the system resolver if `None`.
+	pub resolver: Option<Arc<dyn DnsResolver>>,
+	/// `User-Agent` header sent with every request, unless the offchain worker sets its own. Takes
+	/// precedence over a `User-Agent` in `default_headers`.
+	pub user_agent: Option<String>,
+	/// Headers, as name and value, sent with every request. A header added by the offchain worker
+	/// replaces the default value of the same header. Invalid headers are ignored.
+	pub default_headers: Vec<(String, String)>,
+	/// Names of the channels between the offchain workers and the HTTP worker, under which they
+	/// are reported by the metrics of unbounded channels. Instances of the offchain API that run
+	/// side by side, e.g. for different chains, can be told apart by giving them distinct names.
+	pub channel_names: HttpChannelNames,
+	/// Maximum rate at which requests are started towards each host. Requests started beyond it
+	/// are refused. Unlimited if `None`.
+	pub rate_limit: Option<HttpRateLimit>,
+	/// Automatic retries of the requests without a body, i.e. `GET` and `HEAD` requests, failing
+	/// with an I/O error or a retryable status. The last failure is handed to the offchain worker.
+	/// Requests aren't retried if `None`.
+	pub retry: Option<HttpRetry>,
+	/// Refuses the requests to the hosts failing repeatedly, i.e. with an I/O error or a `5xx` or
+	/// `429` status. Failures are counted across the offchain workers of all blocks. Disabled if
+	/// `None`.
+	pub circuit_breaker: Option<HttpCircuitBreaker>,
+	/// Counters of the events of the pool of connections of the HTTP worker, e.g. to diagnose
+	/// connections being opened and closed too often. Connections aren't tracked if `None`.
+	pub connection_metrics: Option<HttpConnectionMetrics>,
+	/// Maximum number of requests in progress at once, whatever their host, across the offchain
+	/// workers of all blocks. A request counts from `http_request_start` until its response has
+	/// been read entirely or it has failed. Requests started beyond it are refused right away.
+	/// Requests refused by the circuit breaker or the rate limit don't count, and requests refused
+	/// by this limit don't consume the rate limit of their host. Unlimited if `None`.
+	pub max_connections: Option<usize>,
+	/// Minimum number of peers the node must be connected to for offchain workers to start HTTP
+	/// requests, e.g. so that they don't act on a stale view of the chain during startup.
+	/// `http_request_start` fails until then. Not enforced if `None`.
+	pub min_peers: Option<usize>,
+}
+
+impl Default for HttpConfig {
+	fn default() -> Self {
+		HttpConfig {
+			follow_redirects: true,
+			max_redirects: DEFAULT_MAX_REDIRECTS,
+			recording: None,
+			cache_size: 0,
+			min_tls_version: TlsVersion::Tls12,
+			protocol: HttpProtocol::Auto,
+			proxy: HttpProxy::from_env(),
+			resolver: None,
+			user_agent: None,
+			default_headers: Vec::new(),
+			channel_names: HttpChannelNames::default(),
+			rate_limit: None,
+			retry: None,
+			circuit_breaker: None,
+			connection_metrics: None,
+			max_connections: None,
+			min_peers: None,
+		}
+	}
+}
+
+/// Names of the channels between the offchain workers and the HTTP worker, see
+/// [`HttpConfig::channel_names`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HttpChannelNames {
+	/// Name of the channel carrying the requests of the offchain workers to the HTTP worker.
+	pub to_worker: &'static str,
+	/// Name of the channel carrying the responses of the HTTP worker to the offchain workers.
+	pub to_api: &'static str,
+}
+
+impl Default for HttpChannelNames {
+	fn default() -> Self {
+		HttpChannelNames {
+			to_worker: "mpsc_ocw_to_worker",
+			to_api: "mpsc_ocw_to_api",
+		}
+	}
+}
+
+/// Rate at which requests are started towards a single host, see [`HttpConfig::rate_limit`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HttpRateLimit {
+	/// Number of requests per second that can be started in the long run.
+	pub requests_per_second: u32,
+	/// Number of requests that can be started at once, after the host has been left alone for
+	/// long enough.
+	pub burst: u32,
+}
+
+/// Automatic retries of the failing requests, see [`HttpConfig::retry`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpRetry {
+	/// Maximum number of attempts of a single request, including the first one.
+	pub max_attempts: u32,
+	/// Delay before the first retry, doubled for every following one. Each delay is extended by a
+	/// random jitter of up to half of it, and capped at a minute.
+	pub base_delay: Duration,
+	/// Status codes of the responses that are retried. A `Retry-After` header giving a number of
+	/// seconds replaces the delay before the retry.
+	pub retryable_status_codes: Vec<u16>,
+}
+
+impl Default for HttpRetry {
+	fn default() -> Self {
+		HttpRetry {
+			max_attempts: 3,
+			base_delay: Duration::from_millis(100),
+			retryable_status_codes: vec![429, 502, 503],
+		}
+	}
+}
+
+/// Circuit breaker of the hosts failing repeatedly, see [`HttpConfig::circuit_breaker`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HttpCircuitBreaker {
+	/// Number of failures in a row after which the requests to a host are refused.
+	pub failure_threshold: u32,
+	/// How long the requests to a host are refused once it has reached the threshold.
+	pub cooldown: Duration,
+}
+
+/// Version of the TLS protocol.
+///
+/// Versions older than TLS 1.2 are never accepted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TlsVersion {
+	/// TLS 1.2.
+	Tls12,
+	/// TLS 1.3.
+	Tls13,
+}
+
+/// Versions of HTTP the offchain workers may use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HttpProtocol {
+	/// HTTP/2 is used for `https://` requests if the server supports it, HTTP/1.1 otherwise.
+	/// `http://` requests always use HTTP/1.1.
+	Auto,
+	/// Only HTTP/1.1 is used.
+	Http1Only,
+	/// Only HTTP/2 is used. `http://` requests assume the server speaks HTTP/2 without
+	/// negotiation.
+	Http2Only,
+}
+
+/// Proxy configuration of the HTTP requests of offchain workers.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HttpProxy {
+	/// URI of the proxy `http://` requests go through, e.g. `http://proxy.local:3128`.
+	pub http: Option<String>,
+	/// URI of the proxy `https://` requests are tunneled through.
+	pub https: Option<String>,
+	/// User name and password used to authenticate to the `http://` proxy.
+	pub http_credentials: Option<(String, String)>,
+	/// User name and password used to authenticate to the `https://` proxy.
+	pub https_credentials: Option<(String, String)>,
+	/// Hosts that are reached directly. An entry matches the host itself and all its subdomains,
+	/// and `*` matches all hosts.
+	pub no_proxy: Vec<String>,
+}
+
+impl HttpProxy {
+	/// Reads the proxy configuration from the `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY`
+	/// environment variables, or their lowercase variants.
+	///
+	/// Returns `None` if no proxy is configured.
+	pub fn from_env() -> Option<Self> {
+		Self::from_vars(|name| std::env::var(name).ok())
+	}
+
+	/// Same as [`HttpProxy::from_env`], reading the variables through `var`.
+	pub(crate) fn from_vars(var: impl Fn(&str) -> Option<String>) -> Option<Self> {
+		let read = |name: &str| var(name)
+			.or_else(|| var(&name.to_lowercase()))
+			.filter(|value| !value.trim().is_empty());
+
+		// Each proxy only gets the credentials of its own URI.
+		let proxy = |name| match read(name) {
+			Some(uri) => {
+				let (uri, credentials) = split_userinfo(uri.trim());
+				(Some(uri), credentials)
+			},
+			None => (None, None),
+		};
+		let (http, http_credentials) = proxy("HTTP_PROXY");
+		let (https, https_credentials) = proxy("HTTPS_PROXY");
+
+		if http.is_none() && https.is_none() {
+			return None
+		}
+
+		let no_proxy = read("NO_PROXY")
+			.map(|hosts| hosts
+				.split(',')
+				.map(str::trim)
+				.filter(|host| !host.is_empty())
+				.map(String::from)
+				.collect()
+			)
+			.unwrap_or_default();
+
+		Some(HttpProxy { http, https, http_credentials, https_credentials, no_proxy })
+	}
+}
+
+/// Splits the `user:password@` part out of a URI.
+fn split_userinfo(uri: &str) -> (String, Option<(String, String)>) {
+	let authority_start = uri.find("://").map_or(0, |pos| pos + 3);
+	let authority_end = uri[authority_start..].find('/').map_or(uri.len(), |pos| authority_start + pos);
+
+	match uri[authority_start..authority_end].rfind('@') {
+		Some(at) => {
+			let userinfo = &uri[authority_start..authority_start + at];
+			let mut parts = userinfo.splitn(2, ':');
+			let user = parts.next().unwrap_or_default().to_owned();
+			let password = parts.next().unwrap_or_default().to_owned();
+			let uri = format!("{}{}", &uri[..authority_start], &uri[authority_start + at + 1..]);
+			(uri, Some((user, password)))
+		},
+		None => (uri.to_owned(), None),
+	}
+}
+
+/// Resolver of the host names of the HTTP requests of offchain workers.
+pub trait DnsResolver: fmt::Debug + Send + Sync {
+	/// Returns the addresses of `host`. An empty list makes the request fail with an I/O error.
+	///
+	/// This is called from the HTTP worker, and must not block for long.
+	fn resolve(&self, host: &str) -> Vec<IpAddr>;
+
+	/// Returns the TXT records of `name`, as looked up by offchain workers. Returns `None` if the
+	/// resolver can't look up TXT records, which is the default.
+	fn resolve_txt(&self, _name: &str) -> Option<Vec<Vec<u8>>> {
+		None
+	}
+}
+
+/// Resolver answering from a fixed map of host names to addresses, e.g. for tests.
+#[derive(Clone, Debug, Default)]
+pub struct StaticResolver {
+	/// Addresses, by lowercase host name.
+	hosts: HashMap<String, Vec<IpAddr>>,
+	/// TXT records, by lowercase name.
+	txt: HashMap<String, Vec<Vec<u8>>>,
+}
+
+impl StaticResolver {
+	/// Creates a resolver that doesn't know any host.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Resolves `host` to `addresses`, replacing any previous addresses.
+	pub fn with_host(mut self, host: &str, addresses: Vec<IpAddr>) -> Self {
+		self.hosts.insert(host.to_ascii_lowercase(), addresses);
+		self
+	}
+
+	/// Gives `name` the TXT `records`, replacing any previous records.
+	pub fn with_txt(mut self, name: &str, records: Vec<Vec<u8>>) -> Self {
+		self.txt.insert(name.to_ascii_lowercase(), records);
+		self
+	}
+}
+
+impl DnsResolver for StaticResolver {
+	fn resolve(&self, host: &str) -> Vec<IpAddr> {
+		self.hosts.get(&host.to_ascii_lowercase()).cloned().unwrap_or_default()
+	}
+
+	fn resolve_txt(&self, name: &str) -> Option<Vec<Vec<u8>>> {
+		Some(self.txt.get(&name.to_ascii_lowercase()).cloned().unwrap_or_default())
+	}
+}
+
+/// Status of the HTTP worker processing the HTTP requests of offchain workers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HttpWorkerStatus {
+	/// Whether the worker can still process requests. The worker stops once the offchain workers
+	/// it serves are done.
+	pub running: bool,
+	/// Last time the worker has been polled, if ever. The worker is only polled when there is
+	/// something to do, so an idle worker isn't polled. A worker that hasn't been polled for a
+	/// long time while requests are in progress is likely stuck.
+	pub last_poll: Option<Timestamp>,
+}
+
+/// Number of operations on one kind of offchain storage, see [`StorageMetrics`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StorageOperationCounts {
+	/// Number of `local_storage_get` and `local_storage_len` calls.
+	pub get: u64,
+	/// Number of `local_storage_set` calls.
+	pub set: u64,
+	/// Number of `local_storage_clear` calls.
+	pub clear: u64,
+	/// Number of `local_storage_compare_and_set` calls, including the failed ones.
+	pub compare_and_set: u64,
+	/// Number of `local_storage_compare_and_set` calls that didn't set the value.
+	pub compare_and_set_failures: u64,
+}
+
+/// Counters of the operations on the offchain storage, by kind of storage.
+#[derive(Debug, Default)]
+struct StorageCounters {
+	get: AtomicU64,
+	set: AtomicU64,
+	clear: AtomicU64,
+	compare_and_set: AtomicU64,
+	compare_and_set_failures: AtomicU64,
+}
+
+impl StorageCounters {
+	fn counts(&self) -> StorageOperationCounts {
+		StorageOperationCounts {
+			get: self.get.load(Ordering::Relaxed),
+			set: self.set.load(Ordering::Relaxed),
+			clear: self.clear.load(Ordering::Relaxed),
+			compare_and_set: self.compare_and_set.load(Ordering::Relaxed),
+			compare_and_set_failures: self.compare_and_set_failures.load(Ordering::Relaxed),
+		}
+	}
+}
+
+/// Number of events of the pool of connections of the HTTP worker, see
+/// [`HttpConnectionMetrics`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HttpConnectionCounts {
+	/// Number of connections opened.
+	pub opened: u64,
+	/// Number of responses received over a connection that had already been used for an earlier
+	/// response.
+	pub reused: u64,
+	/// Number of connections closed, e.g. evicted from the pool after being idle for too long, or
+	/// closed by the server.
+	pub closed: u64,
+}
+
+/// Shared handle on the counters of the events of the pool of connections of the HTTP worker.
+///
+/// Clones share the same counters, so that the handle given in the [`HttpConfig`] can be read from
+/// elsewhere.
+#[derive(Clone, Debug, Default)]
+pub struct HttpConnectionMetrics {
+	counters: Arc<HttpConnectionCounters>,
+}
+
+/// Counters of the events of the pool of connections.
+#[derive(Debug, Default)]
+struct HttpConnectionCounters {
+	opened: AtomicU64,
+	reused: AtomicU64,
+	closed: AtomicU64,
+}
+
+impl HttpConnectionMetrics {
+	/// Creates a handle on new counters, all at zero.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Returns the number of events of the pool of connections so far.
+	pub fn counts(&self) -> HttpConnectionCounts {
+		HttpConnectionCounts {
+			opened: self.counters.opened.load(Ordering::Relaxed),
+			reused: self.counters.reused.load(Ordering::Relaxed),
+			closed: self.counters.closed.load(Ordering::Relaxed),
+		}
+	}
+
+	/// Returns true if both handles share the same counters.
+	fn same_counters(&self, other: &HttpConnectionMetrics) -> bool {
+		Arc::ptr_eq(&self.counters, &other.counters)
+	}
+
+	fn record_opened(&self) {
+		self.counters.opened.fetch_add(1, Ordering::Relaxed);
+	}
+
+	fn record_reused(&self) {
+		self.counters.reused.fetch_add(1, Ordering::Relaxed);
+	}
+
+	fn record_closed(&self) {
+		self.counters.closed.fetch_add(1, Ordering::Relaxed);
+	}
+}
+
+/// Transparent compression of the values of the persistent offchain storage.
+///
+/// Values written by offchain workers are compressed with zstd if they are long enough and
+/// compression makes them shorter, and decompressed when read. Values stored uncompressed,
+/// e.g. before compression was enabled, are still read correctly. However, compressed values are
+/// returned as is by offchain workers without compression.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StorageCompression {
+	/// Values shorter than this number of bytes are stored uncompressed.
+	pub threshold: usize,
+	/// zstd compression level, from 1 to 21.
+	pub level: i32,
+}
+
+impl Default for StorageCompression {
+	fn default() -> Self {
+		StorageCompression {
+			threshold: 1024,
+			level: 3,
+		}
+	}
+}
+
+/// Handle cancelling the calls to `sleep_until` of offchain workers, e.g. to quickly tear down a
+/// worker parked in a long sleep.
+///
+/// Clones share the same sleeps, and can be used from any thread.
+#[derive(Clone, Debug, Default)]
+pub struct SleepCanceller {
+	/// Wake up the sleeps in progress. Senders of the sleeps that are over are removed as new
+	/// sleeps start.
+	sleeps: Arc<Mutex<Vec<oneshot::Sender<()>>>>,
+}
+
+impl SleepCanceller {
+	/// Cuts short every call to `sleep_until` in progress, which returns right away.
+	///
+	/// Sleeps that are already over and sleeps starting afterwards aren't affected.
+	pub fn cancel_all(&self) {
+		for sleep in self.sleeps.lock().drain(..) {
+			// The sleep may have just ended, in which case there is nobody to notify.
+			let _ = sleep.send(());
+		}
+	}
+
+	/// Registers a new sleep, which is cancelled once the returned receiver resolves.
+	fn register(&self) -> oneshot::Receiver<()> {
+		let (sender, receiver) = oneshot::channel();
+		let mut sleeps = self.sleeps.lock();
+		sleeps.retain(|sleep| !sleep.is_canceled());
+		sleeps.push(sender);
+		receiver
+	}
+}
+
+/// Signal stopping the background processing of the offchain APIs, see `AsyncApi::process`.
+///
+/// Clones share the same signal. Once signalled, processing started afterwards stops right away.
+#[derive(Clone, Debug, Default)]
+pub struct ShutdownSignal {
+	state: Arc<Mutex<ShutdownState>>,
+}
+
+#[derive(Debug, Default)]
+struct ShutdownState {
+	/// True once `signal` has been called.
+	signalled: bool,
+	/// Wake up the processing in progress. Senders of processing that is over are removed as new
+	/// processing starts.
+	waiters: Vec<oneshot::Sender<()>>,
+}
+
+impl ShutdownSignal {
+	/// Stops all the background processing, in progress or to come.
+	pub fn signal(&self) {
+		let mut state = self.state.lock();
+		state.signalled = true;
+		for waiter in state.waiters.drain(..) {
+			// The processing may have just ended, in which case there is nobody to notify.
+			let _ = waiter.send(());
+		}
+	}
+
+	/// Returns true once `signal` has been called.
+	pub fn is_signalled(&self) -> bool {
+		self.state.lock().signalled
+	}
+
+	/// Returns a future resolving once the signal is given.
+	fn wait(&self) -> impl Future<Output = ()> {
+		let (sender, receiver) = oneshot::channel();
+		let mut state = self.state.lock();
+		if state.signalled {
+			let _ = sender.send(());
+		} else {
+			state.waiters.retain(|waiter| !waiter.is_canceled());
+			state.waiters.push(sender);
+		}
+
+		// Once every handle is gone, the signal can't be given anymore.
+		receiver.then(|result| match result {
+			Ok(()) => future::Either::Left(future::ready(())),
+			Err(_) => future::Either::Right(future::pending()),
+		})
+	}
+}
+
+/// Shared handle on the counters of the offchain storage operations of offchain workers.
+///
+/// Clones share the same counters, so that a single handle can be given to the offchain workers of
+/// every block and read from elsewhere.
+#[derive(Clone, Debug, Default)]
+pub struct StorageMetrics {
+	persistent: Arc<StorageCounters>,
+	local: Arc<StorageCounters>,
+}
+
+impl StorageMetrics {
+	/// Creates a handle on new counters, all at zero.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Returns the number of operations on the given kind of storage so far.
+	pub fn counts(&self, kind: StorageKind) -> StorageOperationCounts {
+		self.counters(kind).counts()
+	}
+
+	fn counters(&self, kind: StorageKind) -> &StorageCounters {
+		match kind {
+			StorageKind::PERSISTENT => &self.persistent,
+			StorageKind::LOCAL => &self.local,
+		}
+	}
+}
+
+/// Recording mode of the HTTP interactions of offchain workers.
+///
+/// Requests are identified in the cassette by their method, URI and a hash of their body.
+#[derive(Clone, Debug)]
+pub enum HttpRecording {
+	/// Requests are sent over the network, and the responses are written to the cassette at the
+	/// given path. Responses already present in the cassette are kept, unless they are recorded
+	/// again.
+	Record(PathBuf),
+	/// Responses are served from the cassette at the given path and nothing is sent over the
+	/// network. Requests that can't be found in the cassette fail with an I/O error.
+	Replay(PathBuf),
+}
+
 /// Asynchronous offchain API.
 ///
 /// NOTE this is done to prevent recursive calls into the runtime (which are not supported currently).
@@ -51,25 +630,61 @@ pub(crate) struct Api<Storage> {
 	db: Storage,
 	/// A NetworkState provider.
 	network_state: Arc<dyn NetworkStateInfo + Send + Sync>,
-	/// Is this node a potential validator?
-	is_validator: bool,
+	/// Role of the local node.
+	node_role: NodeRole,
+	/// Maximum duration of a single call to `sleep_until`.
+	max_sleep: Duration,
+	/// Cancels the calls to `sleep_until` in progress.
+	sleep_canceller: SleepCanceller,
+	/// Counters of the storage operations, if they are counted.
+	storage_metrics: Option<StorageMetrics>,
+	/// Compression of the values of the storage, if they are compressed.
+	storage_compression: Option<StorageCompression>,
+	/// Minimum number of connected peers to start HTTP requests, if any.
+	min_peers: Option<usize>,
 	/// Everything HTTP-related is handled by a different struct.
 	http: http::HttpApi,
 }
 
-fn unavailable_yet<R: Default>(name: &str) -> R {
-	error!(
-		"The {:?} API is not available for offchain workers yet. Follow \
-		https://github.com/paritytech/substrate/issues/1458 for details", name
-	);
+/// Minimum interval between two reports of the same API being unavailable.
+const UNAVAILABLE_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+lazy_static! {
+	/// Last time each unavailable API has been reported.
+	static ref UNAVAILABLE_REPORTS: Mutex<HashMap<&'static str, Instant>> = Default::default();
+}
+
+fn unavailable_yet<R: Default>(name: &'static str) -> R {
+	if should_report(&mut UNAVAILABLE_REPORTS.lock(), name, Instant::now()) {
+		error!(
+			"The {:?} API is not available for offchain workers yet. Follow \
+			https://github.com/paritytech/substrate/issues/1458 for details", name
+		);
+	}
 	Default::default()
 }
 
+/// Returns true if `name` hasn't been reported within the last `UNAVAILABLE_REPORT_INTERVAL`,
+/// in which case the report is recorded.
+fn should_report(reports: &mut HashMap<&'static str, Instant>, name: &'static str, now: Instant) -> bool {
+	match reports.get(name) {
+		Some(last) if now.saturating_duration_since(*last) < UNAVAILABLE_REPORT_INTERVAL => false,
+		_ => {
+			reports.insert(name, now);
+			true
+		},
+	}
+}
+
 const LOCAL_DB: &str = "LOCAL (fork-aware) DB";
 
 impl<Storage: OffchainStorage> OffchainExt for Api<Storage> {
+	fn node_role(&self) -> NodeRole {
+		self.node_role
+	}
+
 	fn is_validator(&self) -> bool {
-		self.is_validator
+		self.node_role == NodeRole::Authority
 	}
 
 	fn network_state(&self) -> Result<OpaqueNetworkState, ()> {
@@ -82,12 +697,16 @@ impl<Storage: OffchainStorage> OffchainExt for Api<Storage> {
 		Ok(OpaqueNetworkState::from(state))
 	}
 
+	fn network_listen_addresses(&self) -> Result<Vec<OpaqueMultiaddr>, ()> {
+		Ok(encode_multiaddrs(&self.network_state.listen_addresses()))
+	}
+
 	fn timestamp(&mut self) -> Timestamp {
 		timestamp::now()
 	}
 
 	fn sleep_until(&mut self, deadline: Timestamp) {
-		sleep(timestamp::timestamp_from_now(deadline));
+		futures::executor::block_on(self.sleep_until_async(deadline))
 	}
 
 	fn random_seed(&mut self) -> [u8; 32] {
@@ -95,13 +714,18 @@ impl<Storage: OffchainStorage> OffchainExt for Api<Storage> {
 	}
 
 	fn local_storage_set(&mut self, kind: StorageKind, key: &[u8], value: &[u8]) {
+		self.count_storage(kind, |counters| &counters.set);
 		match kind {
-			StorageKind::PERSISTENT => self.db.set(STORAGE_PREFIX, key, value),
+			StorageKind::PERSISTENT => {
+				let value = self.encode_value(value);
+				self.db.set(STORAGE_PREFIX, key, &value)
+			},
 			StorageKind::LOCAL => unavailable_yet(LOCAL_DB),
 		}
 	}
 
 	fn local_storage_clear(&mut self, kind: StorageKind, key: &[u8]) {
+		self.count_storage(kind, |counters| &counters.clear);
 		match kind {
 			StorageKind::PERSISTENT => self.db.remove(STORAGE_PREFIX, key),
 			StorageKind::LOCAL => unavailable_yet(LOCAL_DB),
@@ -115,17 +739,31 @@ impl<Storage: OffchainStorage> OffchainExt for Api<Storage> {
 		old_value: Option<&[u8]>,
 		new_value: &[u8],
 	) -> bool {
-		match kind {
-			StorageKind::PERSISTENT => {
-				self.db.compare_and_set(STORAGE_PREFIX, key, old_value, new_value)
-			},
+		self.count_storage(kind, |counters| &counters.compare_and_set);
+		let set = match kind {
+			StorageKind::PERSISTENT => self.compare_and_set_value(key, old_value, new_value),
 			StorageKind::LOCAL => unavailable_yet(LOCAL_DB),
+		};
+		if !set {
+			self.count_storage(kind, |counters| &counters.compare_and_set_failures);
 		}
+		set
 	}
 
 	fn local_storage_get(&mut self, kind: StorageKind, key: &[u8]) -> Option<Vec<u8>> {
+		self.count_storage(kind, |counters| &counters.get);
 		match kind {
-			StorageKind::PERSISTENT => self.db.get(STORAGE_PREFIX, key),
+			StorageKind::PERSISTENT => self.get_value(key),
+			StorageKind::LOCAL => unavailable_yet(LOCAL_DB),
+		}
+	}
+
+	fn local_storage_len(&mut self, kind: StorageKind, key: &[u8]) -> Option<usize> {
+		self.count_storage(kind, |counters| &counters.get);
+		match kind {
+			// Any value may have been stored compressed, and its length is only known once it is
+			// decompressed.
+			StorageKind::PERSISTENT => self.get_value(key).map(|value| value.len()),
 			StorageKind::LOCAL => unavailable_yet(LOCAL_DB),
 		}
 	}
@@ -136,6 +774,17 @@ impl<Storage: OffchainStorage> OffchainExt for Api<Storage> {
 		uri: &str,
 		_meta: &[u8]
 	) -> Result<HttpRequestId, ()> {
+		if let Some(min_peers) = self.min_peers {
+			let connected_peers = self.network_state.num_connected_peers();
+			if connected_peers < min_peers {
+				debug!(
+					"Offchain worker started an HTTP request with {} peers out of {} required",
+					connected_peers, min_peers,
+				);
+				return Err(())
+			}
+		}
+
 		self.http.request_start(method, uri)
 	}
 
@@ -148,6 +797,10 @@ impl<Storage: OffchainStorage> OffchainExt for Api<Storage> {
 		self.http.request_add_header(request_id, name, value)
 	}
 
+	fn http_request_ids(&self) -> Vec<HttpRequestId> {
+		self.http.request_ids()
+	}
+
 	fn http_request_write_body(
 		&mut self,
 		request_id: HttpRequestId,
@@ -162,7 +815,15 @@ impl<Storage: OffchainStorage> OffchainExt for Api<Storage> {
 		ids: &[HttpRequestId],
 		deadline: Option<Timestamp>
 	) -> Vec<HttpRequestStatus> {
-		self.http.response_wait(ids, deadline)
+		futures::executor::block_on(self.http_response_wait_async(ids, deadline))
+	}
+
+	fn http_response_wait_any(
+		&mut self,
+		ids: &[HttpRequestId],
+		deadline: Option<Timestamp>
+	) -> Option<(HttpRequestId, HttpRequestStatus)> {
+		self.http.response_wait_any(ids, deadline)
 	}
 
 	fn http_response_headers(
@@ -172,6 +833,13 @@ impl<Storage: OffchainStorage> OffchainExt for Api<Storage> {
 		self.http.response_headers(request_id)
 	}
 
+	fn http_response_trailers(
+		&mut self,
+		request_id: HttpRequestId
+	) -> Vec<(Vec<u8>, Vec<u8>)> {
+		self.http.response_trailers(request_id)
+	}
+
 	fn http_response_read_body(
 		&mut self,
 		request_id: HttpRequestId,
@@ -180,6 +848,100 @@ impl<Storage: OffchainStorage> OffchainExt for Api<Storage> {
 	) -> Result<usize, HttpError> {
 		self.http.response_read_body(request_id, buffer, deadline)
 	}
+
+	fn http_response_stats(&mut self, request_id: HttpRequestId) -> Option<HttpStats> {
+		self.http.response_stats(request_id)
+	}
+
+	#[cfg(feature = "dns")]
+	fn dns_query(&mut self, name: &str, record_type: DnsRecordType) -> Result<Vec<Vec<u8>>, ()> {
+		self.http.dns_query(name, record_type)
+	}
+
+	#[cfg(not(feature = "dns"))]
+	fn dns_query(&mut self, _: &str, _: DnsRecordType) -> Result<Vec<Vec<u8>>, ()> {
+		Err(())
+	}
+}
+
+impl<Storage: OffchainStorage> Api<Storage> {
+	/// Increments the counter picked by `counter` for the given kind of storage, if the storage
+	/// operations are counted.
+	fn count_storage(
+		&self,
+		kind: StorageKind,
+		counter: impl FnOnce(&StorageCounters) -> &AtomicU64,
+	) {
+		if let Some(metrics) = &self.storage_metrics {
+			counter(metrics.counters(kind)).fetch_add(1, Ordering::Relaxed);
+		}
+	}
+
+	/// Returns the bytes to store for `value`, compressed if configured.
+	fn encode_value<'a>(&self, value: &'a [u8]) -> std::borrow::Cow<'a, [u8]> {
+		match &self.storage_compression {
+			Some(storage_compression) => compression::encode(storage_compression, value),
+			None => value.into(),
+		}
+	}
+
+	/// Reads the value stored under `key` in the persistent storage, decompressing it if it has
+	/// been stored compressed.
+	///
+	/// Values are decompressed even if compression is disabled, as they may have been stored
+	/// while it was enabled.
+	fn get_value(&self, key: &[u8]) -> Option<Vec<u8>> {
+		self.db.get(STORAGE_PREFIX, key).map(compression::decode)
+	}
+
+	/// Compares the value stored under `key` in the persistent storage to `old_value` and replaces
+	/// it with `new_value` if they are equal. The stored value is decompressed like in
+	/// `get_value`, and `new_value` is compressed if configured.
+	fn compare_and_set_value(
+		&mut self,
+		key: &[u8],
+		old_value: Option<&[u8]>,
+		new_value: &[u8],
+	) -> bool {
+		let new_value = self.encode_value(new_value).into_owned();
+
+		// The stored bytes of `old_value` may be compressed, so the comparison is made on the
+		// decompressed value, and the bytes that were read are swapped atomically.
+		let stored = self.db.get(STORAGE_PREFIX, key);
+		let current = stored.clone().map(compression::decode);
+		if current.as_deref() != old_value {
+			return false
+		}
+		self.db.compare_and_set(STORAGE_PREFIX, key, stored.as_deref(), &new_value)
+	}
+}
+
+/// Asynchronous versions of the blocking methods of the offchain API.
+///
+/// The synchronous implementation of the offchain externalities blocks on these, but they can
+/// also be `.await`ed directly by offchain code that doesn't run on a dedicated thread.
+impl<Storage> Api<Storage> {
+	/// Asynchronous version of `sleep_until`.
+	pub async fn sleep_until_async(&self, deadline: Timestamp) {
+		let duration = timestamp::timestamp_from_now(deadline);
+		if duration > self.max_sleep {
+			warn!(
+				"Offchain worker asked to sleep for {:?}, only sleeping for {:?}",
+				duration, self.max_sleep,
+			);
+		}
+		let cancelled = self.sleep_canceller.register();
+		future::select(futures_timer::Delay::new(duration.min(self.max_sleep)), cancelled).await;
+	}
+
+	/// Asynchronous version of `http_response_wait`.
+	pub async fn http_response_wait_async(
+		&mut self,
+		ids: &[HttpRequestId],
+		deadline: Option<Timestamp>
+	) -> Vec<HttpRequestStatus> {
+		self.http.response_wait_async(ids, deadline).await
+	}
 }
 
 /// Information about the local node's network state.
@@ -198,23 +960,37 @@ impl NetworkState {
 	}
 }
 
+fn encode_multiaddrs(multiaddrs: &[Multiaddr]) -> Vec<OpaqueMultiaddr> {
+	multiaddrs
+		.iter()
+		.map(|multiaddr| {
+			let e = Encode::encode(&multiaddr.to_string());
+			OpaqueMultiaddr::new(e)
+		})
+		.collect()
+}
+
+fn decode_multiaddrs(multiaddrs: &[OpaqueMultiaddr]) -> Result<Vec<Multiaddr>, ()> {
+	multiaddrs
+		.iter()
+		.map(|enc_multiaddr| -> Result<Multiaddr, ()> {
+			let inner_vec = &enc_multiaddr.0;
+			let bytes = <Vec<u8>>::decode(&mut &inner_vec[..]).map_err(|_| ())?;
+			let multiaddr_str = String::from_utf8(bytes).map_err(|_| ())?;
+			let multiaddr = Multiaddr::from_str(&multiaddr_str).map_err(|_| ())?;
+			Ok(multiaddr)
+		})
+		.collect()
+}
+
 impl From<NetworkState> for OpaqueNetworkState {
 	fn from(state: NetworkState) -> OpaqueNetworkState {
 		let enc = Encode::encode(&state.peer_id.into_bytes());
 		let peer_id = OpaquePeerId::new(enc);
 
-		let external_addresses: Vec<OpaqueMultiaddr> = state
-			.external_addresses
-			.iter()
-			.map(|multiaddr| {
-				let e = Encode::encode(&multiaddr.to_string());
-				OpaqueMultiaddr::new(e)
-			})
-			.collect();
-
 		OpaqueNetworkState {
 			peer_id,
-			external_addresses,
+			external_addresses: encode_multiaddrs(&state.external_addresses),
 		}
 	}
 }
@@ -228,17 +1004,7 @@ impl TryFrom<OpaqueNetworkState> for NetworkState {
 		let bytes: Vec<u8> = Decode::decode(&mut &inner_vec[..]).map_err(|_| ())?;
 		let peer_id = PeerId::from_bytes(bytes).map_err(|_| ())?;
 
-		let external_addresses: Result<Vec<Multiaddr>, Self::Error> = state.external_addresses
-			.iter()
-			.map(|enc_multiaddr| -> Result<Multiaddr, Self::Error> {
-				let inner_vec = &enc_multiaddr.0;
-				let bytes = <Vec<u8>>::decode(&mut &inner_vec[..]).map_err(|_| ())?;
-				let multiaddr_str = String::from_utf8(bytes).map_err(|_| ())?;
-				let multiaddr = Multiaddr::from_str(&multiaddr_str).map_err(|_| ())?;
-				Ok(multiaddr)
-			})
-			.collect();
-		let external_addresses = external_addresses?;
+		let external_addresses = decode_multiaddrs(&state.external_addresses)?;
 
 		Ok(NetworkState {
 			peer_id,
@@ -247,12 +1013,41 @@ impl TryFrom<OpaqueNetworkState> for NetworkState {
 	}
 }
 
+/// Configuration of an instance of the offchain API.
+#[derive(Clone, Debug)]
+pub(crate) struct ApiConfig {
+	/// Configuration of the HTTP capabilities.
+	pub http: HttpConfig,
+	/// Maximum duration an offchain worker can sleep for in a single call to `sleep_until`.
+	pub max_sleep: Duration,
+	/// Cancels the calls to `sleep_until` in progress.
+	pub sleep_canceller: SleepCanceller,
+	/// Counters of the storage operations, if they are counted.
+	pub storage_metrics: Option<StorageMetrics>,
+	/// Compression of the values of the persistent storage, if they are compressed.
+	pub storage_compression: Option<StorageCompression>,
+}
+
+impl Default for ApiConfig {
+	fn default() -> Self {
+		ApiConfig {
+			http: HttpConfig::default(),
+			max_sleep: DEFAULT_MAX_SLEEP,
+			sleep_canceller: SleepCanceller::default(),
+			storage_metrics: None,
+			storage_compression: None,
+		}
+	}
+}
+
 /// Offchain extensions implementation API
 ///
 /// This is the asynchronous processing part of the API.
 pub(crate) struct AsyncApi {
 	/// Everything HTTP-related is handled by a different struct.
 	http: Option<http::HttpWorker>,
+	/// Health of the HTTP worker.
+	http_health: HttpWorkerHealth,
 }
 
 impl AsyncApi {
@@ -260,40 +1055,111 @@ impl AsyncApi {
 	pub fn new<S: OffchainStorage>(
 		db: S,
 		network_state: Arc<dyn NetworkStateInfo + Send + Sync>,
-		is_validator: bool,
+		node_role: NodeRole,
 		shared_client: SharedClient,
+		config: ApiConfig,
 	) -> (Api<S>, Self) {
-		let (http_api, http_worker) = http::http(shared_client);
+		let min_peers = config.http.min_peers;
+		let (http_api, http_worker) = http::http(shared_client, config.http);
 
 		let api = Api {
 			db,
 			network_state,
-			is_validator,
+			node_role,
+			max_sleep: config.max_sleep,
+			sleep_canceller: config.sleep_canceller,
+			storage_metrics: config.storage_metrics,
+			storage_compression: config.storage_compression,
+			min_peers,
 			http: http_api,
 		};
 
 		let async_api = Self {
+			http_health: http_worker.health(),
 			http: Some(http_worker),
 		};
 
 		(api, async_api)
 	}
 
+	/// Returns a handle on the health of the HTTP worker, which remains usable once the worker is
+	/// being processed.
+	pub fn http_worker_health(&self) -> HttpWorkerHealth {
+		self.http_health.clone()
+	}
+
 	/// Run a processing task for the API
-	pub fn process(mut self) -> impl Future<Output = ()> {
+	///
+	/// The returned future drives all the background work of the API, and resolves once all of it
+	/// is done or `shutdown` is signalled. Dropping it stops the background work as well.
+	pub fn process(mut self, shutdown: ShutdownSignal) -> impl Future<Output = ()> {
 		let http = self.http.take().expect("Take invoked only once.");
 
-		http
+		let tasks: Vec<Pin<Box<dyn Future<Output = ()> + Send>>> = vec![Box::pin(http)];
+		future::select(future::join_all(tasks), Box::pin(shutdown.wait())).map(|_| ())
 	}
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
 	use super::*;
-	use std::{convert::{TryFrom, TryInto}, time::SystemTime};
+	use std::{cell::RefCell, convert::{TryFrom, TryInto}, sync::Once, time::SystemTime};
 	use sc_client_db::offchain::LocalStorage;
 	use sc_network::PeerId;
 
+	thread_local! {
+		/// Messages logged by the current thread, if they are being captured.
+		static CAPTURED_LOGS: RefCell<Option<Vec<String>>> = RefCell::new(None);
+	}
+
+	lazy_static! {
+		/// Held by the tests reporting the local storage as unavailable, as the reports are
+		/// rate-limited process-wide.
+		static ref LOCAL_DB_REPORTS: Mutex<()> = Default::default();
+	}
+
+	/// Logger capturing the messages of the threads that asked for it, and passing all the
+	/// messages on to `env_logger`.
+	struct CapturingLogger(env_logger::Logger);
+
+	impl log::Log for CapturingLogger {
+		fn enabled(&self, _: &log::Metadata) -> bool {
+			true
+		}
+
+		fn log(&self, record: &log::Record) {
+			CAPTURED_LOGS.with(|logs| if let Some(logs) = logs.borrow_mut().as_mut() {
+				logs.push(record.args().to_string());
+			});
+			if self.0.matches(record) {
+				self.0.log(record);
+			}
+		}
+
+		fn flush(&self) {
+			self.0.flush();
+		}
+	}
+
+	/// Installs the logger of the tests of the crate. Must be used instead of `env_logger`, as
+	/// there can only be one logger per process.
+	pub(crate) fn init_logger() {
+		static INIT: Once = Once::new();
+		INIT.call_once(|| {
+			let logger = CapturingLogger(env_logger::Builder::from_default_env().build());
+			log::set_boxed_logger(Box::new(logger)).expect("no other logger is installed; qed");
+			log::set_max_level(log::LevelFilter::Trace);
+		});
+	}
+
+	/// Returns the messages logged by the current thread while running `f`.
+	fn capture_logs(f: impl FnOnce()) -> Vec<String> {
+		init_logger();
+		CAPTURED_LOGS.with(|logs| *logs.borrow_mut() = Some(Vec::new()));
+		f();
+		CAPTURED_LOGS.with(|logs| logs.borrow_mut().take().unwrap_or_default())
+	}
+
 	struct MockNetworkStateInfo();
 
 	impl NetworkStateInfo for MockNetworkStateInfo {
@@ -301,13 +1167,21 @@ mod tests {
 			Vec::new()
 		}
 
+		fn listen_addresses(&self) -> Vec<Multiaddr> {
+			Vec::new()
+		}
+
 		fn local_peer_id(&self) -> PeerId {
 			PeerId::random()
 		}
+
+		fn num_connected_peers(&self) -> usize {
+			0
+		}
 	}
 
 	fn offchain_api() -> (Api<LocalStorage>, AsyncApi) {
-		let _ = env_logger::try_init();
+		init_logger();
 		let db = LocalStorage::new_test();
 		let mock = Arc::new(MockNetworkStateInfo());
 		let shared_client = SharedClient::new();
@@ -316,8 +1190,9 @@ mod tests {
 		AsyncApi::new(
 			db,
 			mock,
-			false,
+			NodeRole::Full,
 			shared_client,
+			ApiConfig::default(),
 		)
 	}
 
@@ -337,6 +1212,26 @@ mod tests {
 		assert!(timestamp.unix_millis() >= d);
 	}
 
+	#[test]
+	fn should_report_node_role() {
+		for &(node_role, is_validator) in &[
+			(NodeRole::Authority, true),
+			(NodeRole::Full, false),
+			(NodeRole::Light, false),
+		] {
+			let (api, _) = AsyncApi::new(
+				LocalStorage::new_test(),
+				Arc::new(MockNetworkStateInfo()),
+				node_role,
+				SharedClient::new(),
+				ApiConfig::default(),
+			);
+
+			assert_eq!(api.node_role(), node_role);
+			assert_eq!(api.is_validator(), is_validator);
+		}
+	}
+
 	#[test]
 	fn should_sleep() {
 		let mut api = offchain_api().0;
@@ -355,6 +1250,141 @@ mod tests {
 		assert!(new_now.unix_millis() - 100 >= now.unix_millis());
 	}
 
+	#[test]
+	fn should_sleep_asynchronously() {
+		let api = offchain_api().0;
+
+		// Arrange.
+		let start = std::time::Instant::now();
+		let deadline = timestamp::now().add(sp_core::offchain::Duration::from_millis(500));
+
+		// Act.
+		futures::executor::block_on(futures::future::join(
+			api.sleep_until_async(deadline),
+			api.sleep_until_async(deadline),
+		));
+
+		// Assert.
+		// Both sleeps ran concurrently on the same thread.
+		let elapsed = start.elapsed();
+		assert!(elapsed >= Duration::from_millis(400));
+		assert!(elapsed < Duration::from_millis(1_000));
+	}
+
+	#[test]
+	fn should_clamp_long_sleeps() {
+		let mut api = offchain_api().0;
+		api.max_sleep = Duration::from_millis(100);
+
+		// Arrange.
+		let now = api.timestamp();
+		let deadline = now.add(sp_core::offchain::Duration::from_millis(60 * 60 * 1000));
+
+		// Act.
+		api.sleep_until(deadline);
+		let new_now = api.timestamp();
+
+		// Assert.
+		assert!(new_now.unix_millis() - 100 >= now.unix_millis());
+		assert!(new_now.unix_millis() < deadline.unix_millis());
+		assert!(new_now.unix_millis() - now.unix_millis() < 10_000);
+	}
+
+	#[test]
+	fn should_cancel_sleeps_from_another_thread() {
+		let mut api = offchain_api().0;
+		let canceller = api.sleep_canceller.clone();
+
+		// Cancelling sleeps that are over doesn't affect the next ones.
+		api.sleep_until(timestamp::now());
+		canceller.cancel_all();
+		let start = std::time::Instant::now();
+		api.sleep_until(timestamp::now().add(sp_core::offchain::Duration::from_millis(100)));
+		assert!(start.elapsed() >= Duration::from_millis(100));
+
+		// Act.
+		let start = std::time::Instant::now();
+		let (done_tx, done_rx) = std::sync::mpsc::channel();
+		std::thread::spawn(move || {
+			let deadline = timestamp::now().add(sp_core::offchain::Duration::from_millis(60_000));
+			api.sleep_until(deadline);
+			done_tx.send(()).unwrap();
+		});
+		// The sleep may not have started yet, so cancel until it returns.
+		loop {
+			canceller.cancel_all();
+			match done_rx.recv_timeout(Duration::from_millis(10)) {
+				Ok(()) => break,
+				Err(_) => assert!(start.elapsed() < Duration::from_secs(10)),
+			}
+		}
+
+		// Assert.
+		assert!(start.elapsed() < Duration::from_secs(10));
+	}
+
+	#[test]
+	fn should_stop_processing_on_shutdown() {
+		let (_api, runner) = offchain_api();
+		let shutdown = ShutdownSignal::default();
+
+		// Act.
+		let process = runner.process(shutdown.clone());
+		let (done_tx, done_rx) = std::sync::mpsc::channel();
+		std::thread::spawn(move || {
+			futures::executor::block_on(process);
+			done_tx.send(()).unwrap();
+		});
+		assert!(done_rx.recv_timeout(Duration::from_millis(100)).is_err());
+		shutdown.signal();
+
+		// Assert.
+		done_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+		assert!(shutdown.is_signalled());
+
+		// Processing started after the signal stops right away.
+		let (_api, runner) = offchain_api();
+		futures::executor::block_on(runner.process(shutdown));
+	}
+
+	fn offchain_api_with_resolver(resolver: StaticResolver) -> Api<LocalStorage> {
+		AsyncApi::new(
+			LocalStorage::new_test(),
+			Arc::new(MockNetworkStateInfo()),
+			NodeRole::Full,
+			SharedClient::new(),
+			ApiConfig {
+				http: HttpConfig { resolver: Some(Arc::new(resolver)), ..Default::default() },
+				..Default::default()
+			},
+		).0
+	}
+
+	#[cfg(feature = "dns")]
+	#[test]
+	fn should_query_dns_records() {
+		let resolver = StaticResolver::new()
+			.with_host("svc.invalid", vec!["10.0.0.1".parse().unwrap(), "::1".parse().unwrap()])
+			.with_txt("_svc._tcp.invalid", vec![b"v=1".to_vec(), b"port=9944".to_vec()]);
+		let mut api = offchain_api_with_resolver(resolver);
+
+		assert_eq!(
+			api.dns_query("_svc._tcp.invalid", DnsRecordType::Txt),
+			Ok(vec![b"v=1".to_vec(), b"port=9944".to_vec()]),
+		);
+		assert_eq!(api.dns_query("svc.invalid", DnsRecordType::A), Ok(vec![vec![10, 0, 0, 1]]));
+		assert_eq!(api.dns_query("unknown.invalid", DnsRecordType::Txt), Ok(Vec::new()));
+	}
+
+	#[cfg(not(feature = "dns"))]
+	#[test]
+	fn should_refuse_dns_queries_without_the_feature() {
+		let resolver = StaticResolver::new().with_txt("_svc._tcp.invalid", vec![b"v=1".to_vec()]);
+		let mut api = offchain_api_with_resolver(resolver);
+
+		assert_eq!(api.dns_query("_svc._tcp.invalid", DnsRecordType::Txt), Err(()));
+	}
+
 	#[test]
 	fn should_set_and_get_local_storage() {
 		// given
@@ -370,6 +1400,21 @@ mod tests {
 		assert_eq!(api.local_storage_get(kind, key), Some(b"value".to_vec()));
 	}
 
+	#[test]
+	fn should_get_local_storage_len() {
+		// given
+		let kind = StorageKind::PERSISTENT;
+		let mut api = offchain_api().0;
+		let key = b"test";
+
+		// when
+		assert_eq!(api.local_storage_len(kind, key), None);
+		api.local_storage_set(kind, key, &[1; 4096]);
+
+		// then
+		assert_eq!(api.local_storage_len(kind, key), Some(4096));
+	}
+
 	#[test]
 	fn should_compare_and_set_local_storage() {
 		// given
@@ -402,6 +1447,178 @@ mod tests {
 		assert_eq!(api.local_storage_get(kind, key), Some(b"value".to_vec()));
 	}
 
+	#[test]
+	fn should_compress_local_storage() {
+		// given
+		let kind = StorageKind::PERSISTENT;
+		let db = LocalStorage::new_test();
+		let (mut api, _) = AsyncApi::new(
+			db.clone(),
+			Arc::new(MockNetworkStateInfo()),
+			NodeRole::Full,
+			SharedClient::new(),
+			ApiConfig {
+				storage_compression: Some(StorageCompression { threshold: 64, ..Default::default() }),
+				..Default::default()
+			},
+		);
+		let large = b"value".repeat(100);
+		let mut raw = db.clone();
+		raw.set(STORAGE_PREFIX, b"raw", &large);
+
+		// when
+		api.local_storage_set(kind, b"small", b"value");
+		api.local_storage_set(kind, b"large", &large);
+
+		// then
+		assert_eq!(db.get(STORAGE_PREFIX, b"small"), Some(b"value".to_vec()));
+		assert!(db.get(STORAGE_PREFIX, b"large").unwrap().len() < large.len());
+		assert_eq!(api.local_storage_get(kind, b"small"), Some(b"value".to_vec()));
+		assert_eq!(api.local_storage_get(kind, b"large"), Some(large.clone()));
+		assert_eq!(api.local_storage_len(kind, b"large"), Some(large.len()));
+		// Values stored before compression was enabled are still readable.
+		assert_eq!(api.local_storage_get(kind, b"raw"), Some(large.clone()));
+
+		// when
+		assert!(!api.local_storage_compare_and_set(kind, b"large", Some(b"value"), b"xxx"));
+		assert!(api.local_storage_compare_and_set(kind, b"large", Some(&large), b"xxx"));
+
+		// then
+		assert_eq!(api.local_storage_get(kind, b"large"), Some(b"xxx".to_vec()));
+	}
+
+	#[test]
+	fn should_read_compressed_local_storage_after_disabling_compression() {
+		// given
+		let kind = StorageKind::PERSISTENT;
+		let db = LocalStorage::new_test();
+		let new_api = |storage_compression| AsyncApi::new(
+			db.clone(),
+			Arc::new(MockNetworkStateInfo()),
+			NodeRole::Full,
+			SharedClient::new(),
+			ApiConfig { storage_compression, ..Default::default() },
+		).0;
+		let large = b"value".repeat(100);
+		let mut compressing = new_api(Some(StorageCompression { threshold: 64, ..Default::default() }));
+		compressing.local_storage_set(kind, b"large", &large);
+		compressing.local_storage_set(kind, b"cas", &large);
+		assert!(db.get(STORAGE_PREFIX, b"large").unwrap().len() < large.len());
+
+		// when
+		let mut api = new_api(None);
+
+		// then
+		assert_eq!(api.local_storage_get(kind, b"large"), Some(large.clone()));
+		assert_eq!(api.local_storage_len(kind, b"large"), Some(large.len()));
+		assert!(api.local_storage_compare_and_set(kind, b"cas", Some(&large), b"xxx"));
+		assert_eq!(db.get(STORAGE_PREFIX, b"cas"), Some(b"xxx".to_vec()));
+	}
+
+	#[test]
+	fn should_count_storage_operations() {
+		// given
+		let _reports = LOCAL_DB_REPORTS.lock();
+		let metrics = StorageMetrics::new();
+		let (mut api, _) = AsyncApi::new(
+			LocalStorage::new_test(),
+			Arc::new(MockNetworkStateInfo()),
+			NodeRole::Full,
+			SharedClient::new(),
+			ApiConfig { storage_metrics: Some(metrics.clone()), ..Default::default() },
+		);
+		let kind = StorageKind::PERSISTENT;
+		let key = b"test";
+
+		// when
+		assert_eq!(api.local_storage_get(kind, key), None);
+		api.local_storage_set(kind, key, b"value");
+		assert_eq!(api.local_storage_compare_and_set(kind, key, Some(b"val"), b"xxx"), false);
+		assert_eq!(api.local_storage_compare_and_set(kind, key, Some(b"value"), b"xxx"), true);
+		assert_eq!(api.local_storage_get(kind, key), Some(b"xxx".to_vec()));
+		api.local_storage_clear(kind, key);
+		api.local_storage_clear(kind, key);
+		assert_eq!(api.local_storage_get(StorageKind::LOCAL, key), None);
+
+		// then
+		assert_eq!(metrics.counts(kind), StorageOperationCounts {
+			get: 2,
+			set: 1,
+			clear: 2,
+			compare_and_set: 2,
+			compare_and_set_failures: 1,
+		});
+		assert_eq!(metrics.counts(StorageKind::LOCAL), StorageOperationCounts {
+			get: 1,
+			..Default::default()
+		});
+	}
+
+	#[test]
+	fn should_mutate_local_storage_concurrently() {
+		// given
+		let kind = StorageKind::PERSISTENT;
+		let db = LocalStorage::new_test();
+		let shared_client = SharedClient::new();
+		let key = b"test";
+		let increments = 100u32;
+
+		let increment = |value: Option<Vec<u8>>| {
+			let current = value.map(|v| u32::decode(&mut &v[..]).unwrap()).unwrap_or(0);
+			(current + 1).encode()
+		};
+
+		// when
+		let workers = (0..2).map(|_| {
+			let mut api = AsyncApi::new(
+				db.clone(),
+				Arc::new(MockNetworkStateInfo()),
+				NodeRole::Full,
+				shared_client.clone(),
+				ApiConfig::default(),
+			).0;
+			std::thread::spawn(move || {
+				(0..increments).all(|_| api.local_storage_mutate(kind, key, u32::max_value(), increment))
+			})
+		}).collect::<Vec<_>>();
+
+		// then
+		for worker in workers {
+			assert!(worker.join().unwrap());
+		}
+		assert_eq!(db.get(STORAGE_PREFIX, key), Some((2 * increments).encode()));
+	}
+
+	#[test]
+	fn should_rate_limit_unavailable_reports() {
+		let mut reports = HashMap::new();
+		let now = Instant::now();
+
+		// only the first of many reports in a row goes through.
+		let reported = (0..1000).filter(|_| should_report(&mut reports, LOCAL_DB, now)).count();
+		assert_eq!(reported, 1);
+		assert!(!should_report(&mut reports, LOCAL_DB, now + Duration::from_secs(59)));
+
+		// other APIs are reported independently.
+		assert!(should_report(&mut reports, "other", now));
+
+		// the report goes through again once the interval has elapsed.
+		assert!(should_report(&mut reports, LOCAL_DB, now + UNAVAILABLE_REPORT_INTERVAL));
+	}
+
+	#[test]
+	fn should_log_unavailable_local_storage_once() {
+		let _reports = LOCAL_DB_REPORTS.lock();
+		UNAVAILABLE_REPORTS.lock().remove(LOCAL_DB);
+		let mut api = offchain_api().0;
+
+		let logs = capture_logs(|| for _ in 0..100 {
+			assert_eq!(api.local_storage_get(StorageKind::LOCAL, b"test"), None);
+		});
+
+		assert_eq!(logs.iter().filter(|log| log.contains(LOCAL_DB)).count(), 1);
+	}
+
 	#[test]
 	fn should_convert_network_states() {
 		// given
@@ -421,6 +1638,85 @@ mod tests {
 		assert_eq!(state, converted_back_state);
 	}
 
+	#[test]
+	fn should_refuse_http_requests_without_enough_peers() {
+		struct PeerCount(std::sync::atomic::AtomicUsize);
+
+		impl NetworkStateInfo for PeerCount {
+			fn external_addresses(&self) -> Vec<Multiaddr> {
+				Vec::new()
+			}
+
+			fn listen_addresses(&self) -> Vec<Multiaddr> {
+				Vec::new()
+			}
+
+			fn local_peer_id(&self) -> PeerId {
+				PeerId::random()
+			}
+
+			fn num_connected_peers(&self) -> usize {
+				self.0.load(Ordering::Relaxed)
+			}
+		}
+
+		let network_state = Arc::new(PeerCount(Default::default()));
+		let (mut api, _) = AsyncApi::new(
+			LocalStorage::new_test(),
+			network_state.clone(),
+			NodeRole::Full,
+			SharedClient::new(),
+			ApiConfig {
+				http: HttpConfig { min_peers: Some(2), ..Default::default() },
+				..Default::default()
+			},
+		);
+
+		// Refused while the node hasn't got enough peers.
+		assert_eq!(api.http_request_start("GET", "http://localhost:1234/", &[]), Err(()));
+		network_state.0.store(1, Ordering::Relaxed);
+		assert_eq!(api.http_request_start("GET", "http://localhost:1234/", &[]), Err(()));
+
+		// Allowed once it has.
+		network_state.0.store(2, Ordering::Relaxed);
+		assert!(api.http_request_start("GET", "http://localhost:1234/", &[]).is_ok());
+	}
+
+	#[test]
+	fn should_report_fixed_network_state() {
+		use crate::testing::FixedNetworkState;
+
+		let network_state = || FixedNetworkState::from_seed(1)
+			.with_external_addresses(vec![
+				Multiaddr::try_from("/ip4/1.2.3.4/tcp/30333".to_string()).unwrap(),
+			])
+			.with_listen_addresses(vec![
+				Multiaddr::try_from("/ip4/0.0.0.0/tcp/30333".to_string()).unwrap(),
+			]);
+		let api = || AsyncApi::new(
+			LocalStorage::new_test(),
+			Arc::new(network_state()),
+			NodeRole::Full,
+			SharedClient::new(),
+			ApiConfig::default(),
+		).0;
+		let encoded_network_state = || api().network_state().unwrap().encode();
+
+		// Two APIs built from the same fixed state report the exact same bytes.
+		let encoded = encoded_network_state();
+		assert_eq!(encoded, encoded_network_state());
+
+		let state = NetworkState::try_from(
+			OpaqueNetworkState::decode(&mut &encoded[..]).unwrap()
+		).unwrap();
+		assert_eq!(state.peer_id, network_state().local_peer_id());
+		assert_eq!(state.external_addresses, network_state().external_addresses());
+		assert_ne!(state.peer_id, FixedNetworkState::from_seed(2).local_peer_id());
+
+		let listen_addresses = api().network_listen_addresses().unwrap();
+		assert_eq!(decode_multiaddrs(&listen_addresses), Ok(network_state().listen_addresses()));
+	}
+
 	#[test]
 	fn should_get_random_seed() {
 		// given
@@ -0,0 +1,111 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Transparent compression of the values of the offchain storage.
+//!
+//! Compressed values are stored as a header byte followed by a zstd frame, which itself starts
+//! with a magic number. Values that don't start with both are returned as is, so that values
+//! stored uncompressed, including before compression was enabled, are still read correctly.
+
+use crate::api::StorageCompression;
+use log::warn;
+use std::borrow::Cow;
+
+/// Header byte of the compressed values.
+const HEADER: u8 = 0;
+
+/// Magic number every zstd frame starts with.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Returns the bytes to store for `value`. The value is compressed if it is at least as long as
+/// the threshold and compression makes it shorter.
+pub fn encode<'a>(compression: &StorageCompression, value: &'a [u8]) -> Cow<'a, [u8]> {
+	if value.len() < compression.threshold {
+		return Cow::Borrowed(value)
+	}
+
+	match zstd::encode_all(value, compression.level) {
+		Ok(frame) if frame.len() + 1 < value.len() => {
+			let mut encoded = Vec::with_capacity(frame.len() + 1);
+			encoded.push(HEADER);
+			encoded.extend(frame);
+			Cow::Owned(encoded)
+		},
+		Ok(_) => Cow::Borrowed(value),
+		Err(err) => {
+			warn!("Failed to compress an offchain storage value, storing it as is: {}", err);
+			Cow::Borrowed(value)
+		},
+	}
+}
+
+/// Returns the value stored as `stored`.
+pub fn decode(stored: Vec<u8>) -> Vec<u8> {
+	if !is_compressed(&stored) {
+		return stored
+	}
+
+	// A raw value could start like a compressed one, it is only decompressed if that succeeds.
+	zstd::decode_all(&stored[1..]).unwrap_or(stored)
+}
+
+/// Returns true if `stored` looks like a compressed value.
+fn is_compressed(stored: &[u8]) -> bool {
+	stored.first() == Some(&HEADER) && stored[1..].starts_with(&ZSTD_MAGIC)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn compression(threshold: usize) -> StorageCompression {
+		StorageCompression { threshold, ..Default::default() }
+	}
+
+	#[test]
+	fn round_trips_compressed_values() {
+		let value = b"offchain worker state ".repeat(100);
+
+		let encoded = encode(&compression(64), &value);
+		assert!(matches!(encoded, Cow::Owned(_)));
+		assert!(encoded.len() < value.len());
+		assert_eq!(decode(encoded.into_owned()), value);
+	}
+
+	#[test]
+	fn keeps_values_below_threshold_uncompressed() {
+		let value = b"offchain worker state ".repeat(2);
+
+		let encoded = encode(&compression(64), &value);
+		assert_eq!(&*encoded, &value[..]);
+		assert_eq!(decode(encoded.into_owned()), value);
+	}
+
+	#[test]
+	fn keeps_incompressible_values_uncompressed() {
+		let value = (0..=255u8).collect::<Vec<_>>();
+
+		assert_eq!(&*encode(&compression(0), &value), &value[..]);
+	}
+
+	#[test]
+	fn reads_raw_values_looking_compressed() {
+		let raw = vec![HEADER, 0x28, 0xb5, 0x2f, 0xfd, 1, 2, 3];
+
+		assert_eq!(decode(raw.clone()), raw);
+		assert_eq!(decode(Vec::new()), Vec::<u8>::new());
+	}
+}
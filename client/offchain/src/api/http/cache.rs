@@ -0,0 +1,109 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Cache of the responses to `GET` requests, used by the [`HttpWorker`](super::HttpWorker) to
+//! make conditional requests.
+//!
+//! Responses carrying an `ETag` or a `Last-Modified` header are kept, and later requests to the
+//! same URI are sent with the matching `If-None-Match` or `If-Modified-Since` header. If the
+//! server answers `304 Not Modified`, the cached response is handed to the offchain worker
+//! instead.
+
+use hyper::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use std::collections::VecDeque;
+
+/// Response kept in the cache.
+pub struct CachedResponse {
+	status_code: hyper::StatusCode,
+	headers: hyper::HeaderMap,
+	/// Body of the response. Filled in as the response is being read.
+	pub body: Vec<u8>,
+}
+
+impl CachedResponse {
+	/// Starts caching a response, or returns `None` if it can't be used for conditional
+	/// requests.
+	pub fn new(status_code: hyper::StatusCode, headers: &hyper::HeaderMap) -> Option<Self> {
+		if status_code != hyper::StatusCode::OK ||
+			(!headers.contains_key(ETAG) && !headers.contains_key(LAST_MODIFIED))
+		{
+			return None
+		}
+
+		Some(CachedResponse { status_code, headers: headers.clone(), body: Vec::new() })
+	}
+
+	/// Rebuilds the cached response.
+	pub fn response(&self) -> (hyper::StatusCode, hyper::HeaderMap, hyper::Body) {
+		(self.status_code, self.headers.clone(), hyper::Body::from(self.body.clone()))
+	}
+}
+
+/// Cached responses, by URI. The oldest responses are evicted first.
+pub struct ResponseCache {
+	/// Maximum number of responses in the cache.
+	capacity: usize,
+	/// Cached responses, from the oldest to the most recent.
+	entries: VecDeque<(hyper::Uri, CachedResponse)>,
+}
+
+impl ResponseCache {
+	/// Creates an empty cache holding up to `capacity` responses.
+	pub fn new(capacity: usize) -> Self {
+		ResponseCache { capacity, entries: VecDeque::new() }
+	}
+
+	/// Returns the cached response to a request to `uri`, if any.
+	pub fn get(&self, uri: &hyper::Uri) -> Option<&CachedResponse> {
+		self.entries.iter().find(|(u, _)| u == uri).map(|(_, response)| response)
+	}
+
+	/// Adds the conditional headers matching the cached response to `request`. Returns `false`
+	/// if the request is left untouched, either because nothing has been cached for its URI or
+	/// because it is already conditional.
+	pub fn make_conditional(&self, request: &mut hyper::Request<hyper::Body>) -> bool {
+		if request.headers().contains_key(IF_NONE_MATCH) ||
+			request.headers().contains_key(IF_MODIFIED_SINCE)
+		{
+			return false
+		}
+
+		let cached = match self.get(request.uri()) {
+			Some(cached) => cached,
+			None => return false,
+		};
+
+		let etag = cached.headers.get(ETAG).cloned();
+		let last_modified = cached.headers.get(LAST_MODIFIED).cloned();
+		let headers = request.headers_mut();
+		if let Some(etag) = etag {
+			headers.insert(IF_NONE_MATCH, etag);
+		}
+		if let Some(last_modified) = last_modified {
+			headers.insert(IF_MODIFIED_SINCE, last_modified);
+		}
+		true
+	}
+
+	/// Caches the response to a request to `uri`, replacing any previous one.
+	pub fn insert(&mut self, uri: hyper::Uri, response: CachedResponse) {
+		self.entries.retain(|(u, _)| *u != uri);
+		if self.entries.len() >= self.capacity {
+			self.entries.pop_front();
+		}
+		self.entries.push_back((uri, response));
+	}
+}
@@ -0,0 +1,141 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Storage of recorded HTTP interactions, used by the [`HttpWorker`](super::HttpWorker) when an
+//! [`HttpRecording`] mode is configured.
+//!
+//! A cassette is a file containing the SCALE-encoded list of all the recorded interactions.
+
+use crate::api::HttpRecording;
+use codec::{Encode, Decode};
+use log::error;
+use sp_core::hashing::blake2_256;
+use std::{convert::TryFrom, fs, io, path::PathBuf};
+
+/// Identifies a request within a cassette.
+#[derive(Clone, PartialEq, Eq, Encode, Decode)]
+pub struct RequestKey {
+	method: Vec<u8>,
+	uri: Vec<u8>,
+	body_hash: [u8; 32],
+}
+
+impl RequestKey {
+	/// Builds the key of a request.
+	pub fn new(method: &hyper::Method, uri: &hyper::Uri, body: &[u8]) -> Self {
+		RequestKey {
+			method: method.as_str().as_bytes().to_vec(),
+			uri: uri.to_string().into_bytes(),
+			body_hash: blake2_256(body),
+		}
+	}
+}
+
+/// A request and the response it received.
+#[derive(Encode, Decode)]
+pub struct Interaction {
+	key: RequestKey,
+	status_code: u16,
+	headers: Vec<(Vec<u8>, Vec<u8>)>,
+	/// Body of the response. Filled in as the response is being read.
+	pub body: Vec<u8>,
+}
+
+impl Interaction {
+	/// Starts recording the response to the request identified by `key`.
+	pub fn new(key: RequestKey, status_code: hyper::StatusCode, headers: &hyper::HeaderMap) -> Self {
+		Interaction {
+			key,
+			status_code: status_code.as_u16(),
+			headers: headers
+				.iter()
+				.map(|(name, value)| (name.as_str().as_bytes().to_vec(), value.as_bytes().to_vec()))
+				.collect(),
+			body: Vec::new(),
+		}
+	}
+
+	/// Rebuilds the recorded response. Returns `None` if the cassette contains invalid data.
+	pub fn response(&self) -> Option<(hyper::StatusCode, hyper::HeaderMap, hyper::Body)> {
+		let status_code = hyper::StatusCode::from_u16(self.status_code).ok()?;
+		let mut headers = hyper::HeaderMap::new();
+		for (name, value) in &self.headers {
+			headers.append(
+				hyper::header::HeaderName::try_from(&name[..]).ok()?,
+				hyper::header::HeaderValue::try_from(&value[..]).ok()?,
+			);
+		}
+
+		Some((status_code, headers, hyper::Body::from(self.body.clone())))
+	}
+}
+
+/// Recorded interactions, backed by a file.
+pub struct Cassette {
+	/// Path of the file the interactions are loaded from and saved to.
+	path: PathBuf,
+	/// If true, responses are served from the cassette instead of the network.
+	replaying: bool,
+	/// All the interactions of the cassette.
+	interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+	/// Loads the cassette of the given recording mode.
+	///
+	/// A cassette that can't be loaded is considered empty.
+	pub fn new(recording: &HttpRecording) -> Self {
+		let (path, replaying) = match recording {
+			HttpRecording::Record(path) => (path.clone(), false),
+			HttpRecording::Replay(path) => (path.clone(), true),
+		};
+
+		let interactions = match fs::read(&path) {
+			Ok(bytes) => Vec::<Interaction>::decode(&mut &bytes[..]).unwrap_or_else(|err| {
+				error!("Failed to decode HTTP cassette {}: {}", path.display(), err.what());
+				Vec::new()
+			}),
+			Err(err) if err.kind() == io::ErrorKind::NotFound && !replaying => Vec::new(),
+			Err(err) => {
+				error!("Failed to read HTTP cassette {}: {}", path.display(), err);
+				Vec::new()
+			},
+		};
+
+		Cassette { path, replaying, interactions }
+	}
+
+	/// Returns true if responses must be served from the cassette.
+	pub fn is_replaying(&self) -> bool {
+		self.replaying
+	}
+
+	/// Returns the interaction recorded for the given request, if any.
+	pub fn get(&self, key: &RequestKey) -> Option<&Interaction> {
+		self.interactions.iter().find(|interaction| interaction.key == *key)
+	}
+
+	/// Adds an interaction to the cassette, replacing any previous recording of the same request,
+	/// and saves the cassette.
+	pub fn insert(&mut self, interaction: Interaction) {
+		self.interactions.retain(|i| i.key != interaction.key);
+		self.interactions.push(interaction);
+
+		if let Err(err) = fs::write(&self.path, self.interactions.encode()) {
+			error!("Failed to write HTTP cassette {}: {}", self.path.display(), err);
+		}
+	}
+}
@@ -0,0 +1,100 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Refuses the requests to the hosts failing repeatedly.
+//!
+//! Every host has a count of its failures in a row. Once the count reaches the configured
+//! threshold, the requests to the host are refused until the cooldown is over, after which they
+//! are let through again and the failures are counted anew.
+//!
+//! At most [`MAX_HOSTS`] hosts are kept track of. Once there are that many, the hosts whose
+//! requests aren't being refused are forgotten to make room for new ones.
+
+use crate::api::HttpCircuitBreaker;
+use parking_lot::Mutex;
+use std::{collections::HashMap, time::Instant};
+
+/// Maximum number of hosts whose failures are kept.
+pub const MAX_HOSTS: usize = 1024;
+
+/// Failures of one host.
+#[derive(Default)]
+struct HostFailures {
+	/// Number of failures in a row.
+	count: u32,
+	/// Until when the requests to the host are refused, if they are.
+	open_until: Option<Instant>,
+}
+
+/// Circuit breaker of each host, shared by the offchain workers of all blocks.
+pub struct CircuitBreaker {
+	config: HttpCircuitBreaker,
+	/// Failures, by host. Hosts whose last request succeeded aren't kept.
+	hosts: Mutex<HashMap<String, HostFailures>>,
+}
+
+impl CircuitBreaker {
+	/// Creates a breaker letting the requests to every host through.
+	pub fn new(config: HttpCircuitBreaker) -> Self {
+		CircuitBreaker { config, hosts: Mutex::new(HashMap::new()) }
+	}
+
+	/// Returns the configuration of the breaker.
+	pub fn config(&self) -> HttpCircuitBreaker {
+		self.config
+	}
+
+	/// Returns `false` if the requests to `host` must be refused at `now`.
+	pub fn allows(&self, host: &str, now: Instant) -> bool {
+		match self.hosts.lock().get(&host.to_ascii_lowercase()) {
+			Some(HostFailures { open_until: Some(until), .. }) => now >= *until,
+			_ => true,
+		}
+	}
+
+	/// Reports the outcome of a request to `host` at `now`.
+	pub fn report(&self, host: &str, success: bool, now: Instant) {
+		let host = host.to_ascii_lowercase();
+		let mut hosts = self.hosts.lock();
+		if success {
+			hosts.remove(&host);
+			return
+		}
+
+		if hosts.len() >= MAX_HOSTS && !hosts.contains_key(&host) {
+			// Forgetting the failures of a host whose requests are let through only delays the
+			// moment they get refused.
+			hosts.retain(|_, failures| failures.open_until.map_or(false, |until| now < until));
+			if hosts.len() >= MAX_HOSTS {
+				return
+			}
+		}
+
+		let failures = hosts.entry(host).or_default();
+		if failures.open_until.map_or(false, |until| now >= until) {
+			*failures = HostFailures::default();
+		}
+		failures.count += 1;
+		if failures.count >= self.config.failure_threshold && failures.open_until.is_none() {
+			failures.open_until = Some(now + self.config.cooldown);
+		}
+	}
+}
+
+/// Returns true if a response with `status_code` counts as a failure of its host.
+pub fn is_failure(status_code: hyper::StatusCode) -> bool {
+	status_code.is_server_error() || status_code == hyper::StatusCode::TOO_MANY_REQUESTS
+}
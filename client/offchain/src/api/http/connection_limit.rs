@@ -0,0 +1,65 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Limits the number of requests in progress at once, whatever their host.
+//!
+//! Starting a request takes a permit, which is given back once the request is over. Requests are
+//! refused while all the permits are taken.
+
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+/// Limits the number of requests in progress, shared by the offchain workers of all blocks.
+pub struct ConnectionLimit {
+	max: usize,
+	/// Number of permits taken.
+	taken: Mutex<usize>,
+}
+
+impl ConnectionLimit {
+	/// Creates a limiter with all the permits available.
+	pub fn new(max: usize) -> Self {
+		ConnectionLimit { max, taken: Mutex::new(0) }
+	}
+
+	/// Returns the maximum number of requests in progress.
+	pub fn max(&self) -> usize {
+		self.max
+	}
+
+	/// Takes a permit, held until the returned value is dropped. Returns `None` if all the permits
+	/// are taken, in which case the request must not be started.
+	pub fn try_acquire(self: &Arc<Self>) -> Option<ConnectionPermit> {
+		let mut taken = self.taken.lock();
+		if *taken >= self.max {
+			return None
+		}
+
+		*taken += 1;
+		Some(ConnectionPermit { limit: self.clone() })
+	}
+}
+
+/// Permit of a request in progress, given back when dropped.
+pub struct ConnectionPermit {
+	limit: Arc<ConnectionLimit>,
+}
+
+impl Drop for ConnectionPermit {
+	fn drop(&mut self) {
+		*self.limit.taken.lock() -= 1;
+	}
+}
@@ -0,0 +1,210 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! DNS lookups of offchain workers.
+//!
+//! Lookups go to the [`DnsResolver`] of the [`HttpConfig`](crate::api::HttpConfig) if there is
+//! one. Otherwise, A records are looked up by the system resolver, and TXT records are queried
+//! from the first name server of `/etc/resolv.conf`. Lookups by the system are given up after
+//! [`LOOKUP_TIMEOUT`].
+
+use crate::api::DnsResolver;
+use dns_parser::{Builder, Packet, QueryClass, QueryType, RData, ResponseCode};
+use log::debug;
+use sp_core::offchain::DnsRecordType;
+use std::{
+	fs, io, net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+	sync::mpsc, thread, time::{Duration, Instant},
+};
+
+/// Maximum duration of a lookup by the system.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Size of the buffer the response of a name server is received into.
+const MAX_RESPONSE_LEN: usize = 4096;
+
+/// Looks up the DNS records of `name` on behalf of an offchain worker, with `custom` or with the
+/// system if `None`.
+///
+/// Blocks until the records are found, or for at most [`LOOKUP_TIMEOUT`] if the system looks
+/// them up.
+pub fn query(
+	custom: Option<&dyn DnsResolver>,
+	name: &str,
+	record_type: DnsRecordType,
+) -> Result<Vec<Vec<u8>>, ()> {
+	match (record_type, custom) {
+		(DnsRecordType::A, Some(resolver)) => Ok(ipv4_records(resolver.resolve(name))),
+		(DnsRecordType::A, None) => lookup_host(name).map(ipv4_records),
+		(DnsRecordType::Txt, Some(resolver)) => resolver.resolve_txt(name).ok_or(()),
+		(DnsRecordType::Txt, None) => lookup_txt(name),
+	}
+}
+
+/// Returns the IPv4 addresses among `addresses`, as A records.
+fn ipv4_records(addresses: Vec<IpAddr>) -> Vec<Vec<u8>> {
+	addresses.into_iter()
+		.filter_map(|address| match address {
+			IpAddr::V4(address) => Some(address.octets().to_vec()),
+			IpAddr::V6(_) => None,
+		})
+		.collect()
+}
+
+/// Looks up the addresses of `name` with the system resolver.
+///
+/// The system resolver can't be interrupted, so it runs on a thread of its own that is left to
+/// finish on its own if the lookup times out.
+fn lookup_host(name: &str) -> Result<Vec<IpAddr>, ()> {
+	let (sender, receiver) = mpsc::channel();
+	let name = name.to_owned();
+	thread::Builder::new()
+		.name("offchain-dns".into())
+		.spawn(move || {
+			// Names that can't be resolved simply have no address.
+			let addresses = (name.as_str(), 0).to_socket_addrs()
+				.map(|addresses| addresses.map(|address| address.ip()).collect())
+				.unwrap_or_default();
+			let _ = sender.send(addresses);
+		})
+		.map_err(|err| debug!("Failed to start DNS lookup: {}", err))?;
+
+	receiver.recv_timeout(LOOKUP_TIMEOUT).map_err(|_| ())
+}
+
+/// Queries the TXT records of `name` from the first name server of `/etc/resolv.conf`.
+fn lookup_txt(name: &str) -> Result<Vec<Vec<u8>>, ()> {
+	let resolv_conf = fs::read_to_string("/etc/resolv.conf")
+		.map_err(|err| debug!("Failed to read the name servers: {}", err))?;
+	let nameserver = first_nameserver(&resolv_conf).ok_or(())?;
+
+	query_txt(nameserver, name, Instant::now() + LOOKUP_TIMEOUT)
+		.map_err(|err| debug!("Failed to look up the TXT records of {}: {}", name, err))
+}
+
+/// Returns the first name server listed in the content of a `resolv.conf` file.
+fn first_nameserver(resolv_conf: &str) -> Option<SocketAddr> {
+	resolv_conf.lines()
+		.filter_map(|line| {
+			let mut words = line.split_whitespace();
+			match (words.next(), words.next()) {
+				(Some("nameserver"), Some(address)) => address.parse::<IpAddr>().ok(),
+				_ => None,
+			}
+		})
+		.next()
+		.map(|address| SocketAddr::new(address, 53))
+}
+
+/// Sends a recursive query for the TXT records of `name` to `nameserver` over UDP, and waits for
+/// the answer until `deadline`.
+///
+/// The character strings of each record are concatenated.
+fn query_txt(nameserver: SocketAddr, name: &str, deadline: Instant) -> io::Result<Vec<Vec<u8>>> {
+	let id = rand::random::<u16>();
+	let mut builder = Builder::new_query(id, true);
+	builder.add_question(name.trim_end_matches('.'), false, QueryType::TXT, QueryClass::IN);
+	let query = builder.build()
+		.map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "DNS query too long"))?;
+
+	let local_address: IpAddr = match nameserver {
+		SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+		SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+	};
+	let socket = UdpSocket::bind(SocketAddr::new(local_address, 0))?;
+	socket.connect(nameserver)?;
+	socket.send(&query)?;
+
+	let mut buffer = vec![0; MAX_RESPONSE_LEN];
+	loop {
+		let remaining = deadline.checked_duration_since(Instant::now())
+			.filter(|remaining| *remaining > Duration::from_millis(0))
+			.ok_or(io::ErrorKind::TimedOut)?;
+		socket.set_read_timeout(Some(remaining))?;
+
+		let len = socket.recv(&mut buffer)?;
+		let packet = match Packet::parse(&buffer[..len]) {
+			Ok(packet) if packet.header.id == id && !packet.header.query => packet,
+			// Datagrams that don't answer the query are ignored.
+			_ => continue,
+		};
+
+		match packet.header.response_code {
+			ResponseCode::NoError => {},
+			// Names that don't exist simply have no records.
+			ResponseCode::NameError => return Ok(Vec::new()),
+			code => return Err(io::Error::new(
+				io::ErrorKind::Other,
+				format!("name server answered {:?}", code),
+			)),
+		}
+		if packet.header.truncated {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated DNS response"))
+		}
+
+		return Ok(packet.answers.iter()
+			.filter_map(|answer| match &answer.data {
+				RData::TXT(record) => Some(record.iter().flatten().copied().collect()),
+				_ => None,
+			})
+			.collect())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn finds_first_nameserver() {
+		let resolv_conf = "# generated\nsearch local\nnameserver  10.0.0.53\nnameserver ::1\n";
+		assert_eq!(first_nameserver(resolv_conf), Some("10.0.0.53:53".parse().unwrap()));
+		assert_eq!(first_nameserver("search local\nnameserver invalid\n"), None);
+	}
+
+	#[test]
+	fn queries_txt_records() {
+		// The mock name server answers the query with a TXT record made of two strings.
+		let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+		let nameserver = server.local_addr().unwrap();
+		let handle = thread::spawn(move || {
+			let mut buffer = [0; 512];
+			let (len, client) = server.recv_from(&mut buffer).unwrap();
+			let mut response = buffer[..len].to_vec();
+			// A response, with one answer pointing at the name of the question.
+			response[2] |= 0x80;
+			response[7] = 1;
+			response.extend_from_slice(&[0xc0, 0x0c, 0, 16, 0, 1, 0, 0, 0, 60, 0, 9]);
+			response.extend_from_slice(b"\x03v=1\x04p=42");
+			server.send_to(&response, client).unwrap();
+		});
+
+		let deadline = Instant::now() + LOOKUP_TIMEOUT;
+		let records = query_txt(nameserver, "_svc._tcp.example.", deadline).unwrap();
+		assert_eq!(records, vec![b"v=1p=42".to_vec()]);
+		handle.join().unwrap();
+	}
+
+	#[test]
+	fn txt_queries_time_out() {
+		// Nothing answers on this socket.
+		let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+		let deadline = Instant::now() + Duration::from_millis(100);
+
+		let err = query_txt(server.local_addr().unwrap(), "example", deadline).unwrap_err();
+		assert!(matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut));
+	}
+}
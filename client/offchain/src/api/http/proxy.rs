@@ -0,0 +1,310 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Routing of the HTTP requests of offchain workers through a proxy.
+//!
+//! Plain `http://` requests are sent to the proxy in absolute form, while `https://` requests go
+//! through a tunnel opened with a `CONNECT` request, so that TLS is still negotiated with the
+//! destination.
+
+use crate::api::{HttpConnectionMetrics, HttpProxy};
+use super::resolver::Resolver;
+use hyper::{
+	client::{HttpConnector, connect::{Connected, Connection}},
+	header::{HeaderValue, PROXY_AUTHORIZATION},
+	service::Service,
+	Uri,
+};
+use log::warn;
+use std::{
+	error, future::Future, io, pin::Pin,
+	sync::{Arc, atomic::{AtomicBool, Ordering}},
+	task::{Context, Poll},
+};
+use tokio::{io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _}, net::TcpStream};
+
+/// Maximum size of the response of a proxy to a `CONNECT` request.
+const MAX_CONNECT_RESPONSE_LEN: usize = 8 * 1024;
+
+/// A proxy, along with the credentials to authenticate to it.
+#[derive(Debug)]
+struct ProxyServer {
+	uri: Uri,
+	/// Value of the `Proxy-Authorization` header sent to this proxy.
+	authorization: Option<HeaderValue>,
+}
+
+impl ProxyServer {
+	/// Parses the URI of a proxy. Returns `None` if there is no proxy, or if its URI is invalid.
+	fn parse(uri: &Option<String>, credentials: &Option<(String, String)>) -> Option<Self> {
+		let uri = match uri.as_ref()?.parse::<Uri>() {
+			Ok(uri) if uri.host().is_some() => uri,
+			_ => {
+				warn!("Ignoring invalid HTTP proxy URI {:?}", uri);
+				return None
+			},
+		};
+
+		let authorization = credentials.as_ref().and_then(|(user, password)| {
+			let encoded = base64::encode(format!("{}:{}", user, password));
+			HeaderValue::from_str(&format!("Basic {}", encoded)).ok()
+		});
+
+		Some(ProxyServer { uri, authorization })
+	}
+}
+
+/// Proxy configuration, parsed.
+#[derive(Debug)]
+pub struct Proxy {
+	/// Configuration the proxy has been built from.
+	config: HttpProxy,
+	/// Proxy for `http://` requests.
+	http: Option<ProxyServer>,
+	/// Proxy for `https://` requests.
+	https: Option<ProxyServer>,
+}
+
+impl Proxy {
+	/// Parses a proxy configuration. Invalid proxy URIs are ignored.
+	pub fn new(config: HttpProxy) -> Self {
+		Proxy {
+			http: ProxyServer::parse(&config.http, &config.http_credentials),
+			https: ProxyServer::parse(&config.https, &config.https_credentials),
+			config,
+		}
+	}
+
+	/// Returns the configuration the proxy has been built from.
+	pub fn config(&self) -> &HttpProxy {
+		&self.config
+	}
+
+	/// Returns the proxy the given destination must be reached through, if any.
+	fn proxy_for(&self, uri: &Uri) -> Option<&ProxyServer> {
+		let proxy = match uri.scheme_str() {
+			Some("https") => self.https.as_ref(),
+			_ => self.http.as_ref(),
+		}?;
+
+		let host = uri.host()?.trim_end_matches('.');
+		let bypassed = self.config.no_proxy.iter().any(|entry| {
+			let entry = entry.trim().trim_start_matches('.');
+			entry == "*" || host.eq_ignore_ascii_case(entry) ||
+				host.to_ascii_lowercase().ends_with(&format!(".{}", entry.to_ascii_lowercase()))
+		});
+
+		if bypassed {
+			None
+		} else {
+			Some(proxy)
+		}
+	}
+
+	/// Adds the proxy credentials to a request sent in absolute form to a proxy.
+	///
+	/// Requests going through a tunnel are left untouched, as the credentials have been sent with
+	/// the `CONNECT` request.
+	pub fn authorize(&self, request: &mut hyper::Request<hyper::Body>) {
+		if request.uri().scheme_str() == Some("https") {
+			return
+		}
+
+		let authorization = self.proxy_for(request.uri())
+			.and_then(|proxy| proxy.authorization.as_ref());
+		if let Some(authorization) = authorization {
+			request.headers_mut().insert(PROXY_AUTHORIZATION, authorization.clone());
+		}
+	}
+}
+
+/// Connector opening the connections either directly or through the configured proxy.
+#[derive(Clone)]
+pub struct ProxyConnector {
+	/// Connector used to open the TCP connections.
+	http: HttpConnector<Resolver>,
+	/// Proxy to go through, if any.
+	proxy: Option<Arc<Proxy>>,
+	/// Counters of the events of the connections, if they are tracked.
+	metrics: Option<HttpConnectionMetrics>,
+}
+
+impl ProxyConnector {
+	/// Creates a connector going through `proxy`, if any, and resolving the host names with
+	/// `resolver`. The connections are tracked in `metrics`, if any.
+	pub fn new(
+		proxy: Option<Arc<Proxy>>,
+		resolver: Resolver,
+		metrics: Option<HttpConnectionMetrics>,
+	) -> Self {
+		let mut http = HttpConnector::new_with_resolver(resolver);
+		http.enforce_http(false);
+		ProxyConnector { http, proxy, metrics }
+	}
+}
+
+impl Service<Uri> for ProxyConnector {
+	type Response = ProxyStream;
+	type Error = Box<dyn error::Error + Send + Sync>;
+	type Future = Pin<Box<dyn Future<Output = Result<ProxyStream, Self::Error>> + Send>>;
+
+	fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+		self.http.poll_ready(cx).map_err(Into::into)
+	}
+
+	fn call(&mut self, destination: Uri) -> Self::Future {
+		let mut http = self.http.clone();
+		let proxy = self.proxy.as_ref().and_then(|proxy| {
+			proxy.proxy_for(&destination)
+				.map(|proxy| (proxy.uri.clone(), proxy.authorization.clone()))
+		});
+		let metrics = self.metrics.clone();
+
+		Box::pin(async move {
+			let (inner, proxied) = match proxy {
+				None => (http.call(destination).await?, false),
+				Some((proxy_uri, authorization)) => {
+					let mut inner = http.call(proxy_uri).await?;
+					if destination.scheme_str() == Some("https") {
+						tunnel(&mut inner, &destination, authorization).await?;
+						(inner, false)
+					} else {
+						(inner, true)
+					}
+				},
+			};
+
+			Ok(ProxyStream { inner, proxied, connection_use: metrics.map(ConnectionUse::open) })
+		})
+	}
+}
+
+/// Asks the proxy at the other end of `stream` to open a tunnel to `destination`.
+async fn tunnel(
+	stream: &mut TcpStream,
+	destination: &Uri,
+	authorization: Option<HeaderValue>,
+) -> io::Result<()> {
+	let host = destination.host()
+		.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "missing destination host"))?;
+	let port = destination.port_u16().unwrap_or(443);
+
+	let mut request = format!("CONNECT {0}:{1} HTTP/1.1\r\nHost: {0}:{1}\r\n", host, port).into_bytes();
+	if let Some(authorization) = authorization {
+		request.extend_from_slice(b"Proxy-Authorization: ");
+		request.extend_from_slice(authorization.as_bytes());
+		request.extend_from_slice(b"\r\n");
+	}
+	request.extend_from_slice(b"\r\n");
+	stream.write_all(&request).await?;
+
+	// Read the response head byte by byte, so that nothing sent by the destination after the
+	// tunnel has been established is consumed.
+	let mut response = Vec::new();
+	while !response.ends_with(b"\r\n\r\n") {
+		if response.len() >= MAX_CONNECT_RESPONSE_LEN {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "proxy response too long"))
+		}
+
+		let mut byte = [0];
+		if stream.read(&mut byte).await? == 0 {
+			return Err(io::ErrorKind::UnexpectedEof.into())
+		}
+		response.push(byte[0]);
+	}
+
+	let status_line = response.split(|b| *b == b'\r').next().unwrap_or_default();
+	match status_line.split(|b| *b == b' ').nth(1) {
+		Some(b"200") => Ok(()),
+		_ => Err(io::Error::new(
+			io::ErrorKind::Other,
+			format!("proxy refused tunnel: {}", String::from_utf8_lossy(status_line)),
+		)),
+	}
+}
+
+/// Connection opened by the [`ProxyConnector`].
+pub struct ProxyStream {
+	inner: TcpStream,
+	/// True if requests must be sent to the proxy in absolute form.
+	proxied: bool,
+	/// Uses of the connection, if connections are tracked.
+	connection_use: Option<ConnectionUse>,
+}
+
+impl Connection for ProxyStream {
+	fn connected(&self) -> Connected {
+		let connected = self.inner.connected().proxy(self.proxied);
+		// The client attaches the extra to every response received over the connection.
+		match &self.connection_use {
+			Some(connection_use) => connected.extra(connection_use.clone()),
+			None => connected,
+		}
+	}
+}
+
+impl Drop for ProxyStream {
+	fn drop(&mut self) {
+		if let Some(connection_use) = &self.connection_use {
+			connection_use.metrics.record_closed();
+		}
+	}
+}
+
+/// Tracks the uses of a connection in the [`HttpConnectionMetrics`].
+///
+/// Found in the extensions of the responses received over a tracked connection.
+#[derive(Clone)]
+pub struct ConnectionUse {
+	metrics: HttpConnectionMetrics,
+	/// True once a response has been received over the connection.
+	used: Arc<AtomicBool>,
+}
+
+impl ConnectionUse {
+	/// Records a connection that has just been opened.
+	fn open(metrics: HttpConnectionMetrics) -> Self {
+		metrics.record_opened();
+		ConnectionUse { metrics, used: Arc::new(AtomicBool::new(false)) }
+	}
+
+	/// Records a response received over the connection.
+	pub fn record_response(&self) {
+		if self.used.swap(true, Ordering::Relaxed) {
+			self.metrics.record_reused();
+		}
+	}
+}
+
+impl AsyncRead for ProxyStream {
+	fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+		Pin::new(&mut self.inner).poll_read(cx, buf)
+	}
+}
+
+impl AsyncWrite for ProxyStream {
+	fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+		Pin::new(&mut self.inner).poll_write(cx, buf)
+	}
+
+	fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.inner).poll_flush(cx)
+	}
+
+	fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.inner).poll_shutdown(cx)
+	}
+}
@@ -0,0 +1,83 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Limits the rate at which requests are started towards each host.
+//!
+//! Every host has a bucket of tokens, filled at the configured rate up to the configured burst.
+//! Starting a request takes a token from the bucket of its host, and is refused if the bucket is
+//! empty.
+
+use crate::api::HttpRateLimit;
+use parking_lot::Mutex;
+use std::{collections::HashMap, time::Instant};
+
+/// Beyond this number of buckets, the full ones are dropped, as they are the same as new ones.
+const MAX_BUCKETS: usize = 1024;
+
+/// Bucket of tokens of one host.
+struct Bucket {
+	/// Number of requests that can be started right away.
+	tokens: f64,
+	/// Last time the bucket was filled.
+	filled_at: Instant,
+}
+
+/// Limits the rate of the requests to each host, shared by the offchain workers of all blocks.
+pub struct RateLimiter {
+	limit: HttpRateLimit,
+	/// Buckets, by host.
+	buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+	/// Creates a limiter with all the buckets full.
+	pub fn new(limit: HttpRateLimit) -> Self {
+		RateLimiter { limit, buckets: Mutex::new(HashMap::new()) }
+	}
+
+	/// Returns the limit enforced by the limiter.
+	pub fn limit(&self) -> HttpRateLimit {
+		self.limit
+	}
+
+	/// Takes a token from the bucket of `host` at `now`. Returns `false` if the bucket is empty,
+	/// in which case the request must not be started.
+	pub fn try_acquire(&self, host: &str, now: Instant) -> bool {
+		let host = host.to_ascii_lowercase();
+		let burst = f64::from(self.limit.burst);
+		let rate = f64::from(self.limit.requests_per_second);
+		let mut buckets = self.buckets.lock();
+
+		if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&host) {
+			buckets.retain(|_, bucket| {
+				let elapsed = now.saturating_duration_since(bucket.filled_at).as_secs_f64();
+				bucket.tokens + elapsed * rate < burst
+			});
+		}
+
+		let bucket = buckets.entry(host)
+			.or_insert_with(|| Bucket { tokens: burst, filled_at: now });
+		let elapsed = now.saturating_duration_since(bucket.filled_at).as_secs_f64();
+		bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+		bucket.filled_at = now;
+
+		if bucket.tokens < 1.0 {
+			return false
+		}
+		bucket.tokens -= 1.0;
+		true
+	}
+}
@@ -0,0 +1,74 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Resolution of the host names of the HTTP requests, either by the system or by the
+//! [`DnsResolver`] of the [`HttpConfig`](crate::api::HttpConfig).
+
+use crate::api::DnsResolver;
+use hyper::{client::connect::dns::{GaiResolver, Name}, service::Service};
+use std::{future::Future, io, net::IpAddr, pin::Pin, sync::Arc, task::{Context, Poll}, vec};
+
+/// Resolver used by the connector of the HTTP client.
+#[derive(Clone)]
+pub struct Resolver {
+	/// Resolver configured by the user, if any.
+	custom: Option<Arc<dyn DnsResolver>>,
+	/// System resolver, used if no resolver has been configured.
+	system: GaiResolver,
+}
+
+impl Resolver {
+	/// Creates a resolver delegating to `custom`, or to the system resolver if `None`.
+	pub fn new(custom: Option<Arc<dyn DnsResolver>>) -> Self {
+		Resolver { custom, system: GaiResolver::new() }
+	}
+}
+
+impl Service<Name> for Resolver {
+	type Response = vec::IntoIter<IpAddr>;
+	type Error = io::Error;
+	type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+	fn poll_ready(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+		match self.custom {
+			Some(_) => Poll::Ready(Ok(())),
+			None => self.system.poll_ready(cx),
+		}
+	}
+
+	fn call(&mut self, name: Name) -> Self::Future {
+		match &self.custom {
+			Some(resolver) => {
+				let addresses = resolver.resolve(name.as_str());
+				let result = if addresses.is_empty() {
+					Err(io::Error::new(
+						io::ErrorKind::NotFound,
+						format!("no address found for {}", name.as_str()),
+					))
+				} else {
+					Ok(addresses.into_iter())
+				};
+				Box::pin(futures::future::ready(result))
+			},
+			None => {
+				let addresses = self.system.call(name);
+				Box::pin(async move {
+					Ok(addresses.await?.collect::<Vec<_>>().into_iter())
+				})
+			},
+		}
+	}
+}
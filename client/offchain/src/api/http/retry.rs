@@ -0,0 +1,98 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Retries of the requests failing with a transient error.
+//!
+//! Like redirects, only requests without a body are retried, as the body of a request is
+//! streamed from the [`HttpApi`](super::HttpApi) and can't be sent a second time.
+
+use crate::api::HttpRetry;
+use std::time::Duration;
+
+/// Delays between attempts are never longer than this.
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// What the worker needs to remember about a dispatched request in order to retry it.
+pub struct Retry {
+	/// Method of the request.
+	method: hyper::Method,
+	/// URI of the request.
+	uri: hyper::Uri,
+	/// Headers of the request.
+	headers: hyper::HeaderMap,
+	/// Number of attempts made so far.
+	attempts: u32,
+}
+
+impl Retry {
+	/// Prepares the retries of `request`, which has been attempted once. Returns `None` if the
+	/// request can't be retried.
+	pub fn new(request: &hyper::Request<hyper::Body>) -> Option<Self> {
+		if request.method() != hyper::Method::GET && request.method() != hyper::Method::HEAD {
+			return None
+		}
+
+		Some(Retry {
+			method: request.method().clone(),
+			uri: request.uri().clone(),
+			headers: request.headers().clone(),
+			attempts: 1,
+		})
+	}
+
+	/// Host of the request.
+	pub fn host(&self) -> &str {
+		self.uri.host().unwrap_or_default()
+	}
+
+	/// Counts a new attempt and returns how long to wait before making it, or `None` if the
+	/// request has been attempted as many times as `config` allows.
+	///
+	/// `headers` are the headers of the response that failed, if any.
+	pub fn next_attempt(
+		&mut self,
+		config: &HttpRetry,
+		headers: Option<&hyper::HeaderMap>,
+	) -> Option<Duration> {
+		if self.attempts >= config.max_attempts {
+			return None
+		}
+		self.attempts += 1;
+
+		let delay = headers.and_then(retry_after).unwrap_or_else(|| {
+			// The first retry waits for the base delay, and every following one twice as long.
+			let factor = 2u32.checked_pow(self.attempts - 2).unwrap_or(u32::max_value());
+			let delay = config.base_delay.checked_mul(factor).unwrap_or(MAX_DELAY);
+			delay.mul_f64(1.0 + rand::random::<f64>() / 2.0)
+		});
+		Some(delay.min(MAX_DELAY))
+	}
+
+	/// Builds the request of the next attempt.
+	pub fn request(&self) -> hyper::Request<hyper::Body> {
+		let mut request = hyper::Request::new(hyper::Body::empty());
+		*request.method_mut() = self.method.clone();
+		*request.uri_mut() = self.uri.clone();
+		*request.headers_mut() = self.headers.clone();
+		request
+	}
+}
+
+/// Returns the delay of the `Retry-After` header of a response, if it gives a number of seconds.
+fn retry_after(headers: &hyper::HeaderMap) -> Option<Duration> {
+	let value = headers.get(hyper::header::RETRY_AFTER)?.to_str().ok()?;
+	value.trim().parse().ok().map(Duration::from_secs)
+}
@@ -16,7 +16,10 @@
 
 //! Contains the same API as the `http` module, except that everything returns an error.
 
-use sp_core::offchain::{HttpRequestId, Timestamp, HttpRequestStatus, HttpError};
+use crate::api::{HttpConfig, HttpWorkerStatus};
+#[cfg(feature = "dns")]
+use sp_core::offchain::DnsRecordType;
+use sp_core::offchain::{HttpRequestId, Timestamp, HttpRequestStatus, HttpError, HttpStats};
 use std::{future::Future, pin::Pin, task::Context, task::Poll};
 
 /// Wrapper struct (wrapping nothing in case of http_dummy) used for keeping the hyper_rustls client running.
@@ -27,10 +30,14 @@ impl SharedClient {
 	pub fn new() -> Self {
 		Self
 	}
+
+	pub fn with_config(_: &HttpConfig) -> Self {
+		Self
+	}
 }
 
 /// Creates a pair of [`HttpApi`] and [`HttpWorker`].
-pub fn http(_: SharedClient) -> (HttpApi, HttpWorker) {
+pub fn http(_: SharedClient, _: HttpConfig) -> (HttpApi, HttpWorker) {
 	(HttpApi, HttpWorker)
 }
 
@@ -42,6 +49,24 @@ pub struct HttpApi;
 #[derive(Debug)]
 pub struct HttpWorker;
 
+/// Dummy implementation of the health of the HTTP worker.
+#[derive(Clone, Debug)]
+pub struct HttpWorkerHealth;
+
+impl HttpWorkerHealth {
+	/// The dummy worker never processes anything.
+	pub fn status(&self) -> HttpWorkerStatus {
+		HttpWorkerStatus { running: false, last_poll: None }
+	}
+}
+
+impl HttpWorker {
+	/// Returns a handle on the health of the worker.
+	pub fn health(&self) -> HttpWorkerHealth {
+		HttpWorkerHealth
+	}
+}
+
 impl HttpApi {
 	/// Mimics the corresponding method in the offchain API.
 	pub fn request_start(
@@ -64,6 +89,12 @@ impl HttpApi {
 			never be called; qed")
 	}
 
+	/// Mimics the corresponding method in the offchain API.
+	pub fn request_ids(&self) -> Vec<HttpRequestId> {
+		// No request can be started.
+		Vec::new()
+	}
+
 	/// Mimics the corresponding method in the offchain API.
 	pub fn request_write_body(
 		&mut self,
@@ -89,6 +120,29 @@ impl HttpApi {
 		}
 	}
 
+	/// Mimics the corresponding method in the offchain API.
+	pub async fn response_wait_async(
+		&mut self,
+		requests: &[HttpRequestId],
+		deadline: Option<Timestamp>
+	) -> Vec<HttpRequestStatus> {
+		self.response_wait(requests, deadline)
+	}
+
+	/// Mimics the corresponding method in the offchain API.
+	pub fn response_wait_any(
+		&mut self,
+		requests: &[HttpRequestId],
+		_: Option<Timestamp>
+	) -> Option<(HttpRequestId, HttpRequestStatus)> {
+		if requests.is_empty() {
+			None
+		} else {
+			unreachable!("Creating a request always fails, thus the list of requests should \
+				always be empty; qed")
+		}
+	}
+
 	/// Mimics the corresponding method in the offchain API.
 	pub fn response_headers(
 		&mut self,
@@ -98,6 +152,15 @@ impl HttpApi {
 			never be called; qed")
 	}
 
+	/// Mimics the corresponding method in the offchain API.
+	pub fn response_trailers(
+		&mut self,
+		_: HttpRequestId
+	) -> Vec<(Vec<u8>, Vec<u8>)> {
+		unreachable!("Creating a request always fails, thus this function will \
+			never be called; qed")
+	}
+
 	/// Mimics the corresponding method in the offchain API.
 	pub fn response_read_body(
 		&mut self,
@@ -108,6 +171,22 @@ impl HttpApi {
 		unreachable!("Creating a request always fails, thus this function will \
 			never be called; qed")
 	}
+
+	/// Mimics the corresponding method in the offchain API.
+	pub fn response_stats(&mut self, _: HttpRequestId) -> Option<HttpStats> {
+		None
+	}
+
+	/// Mimics the corresponding method in the offchain API.
+	#[cfg(feature = "dns")]
+	pub fn dns_query(&self, _: &str, _: DnsRecordType) -> Result<Vec<Vec<u8>>, ()> {
+		Err(())
+	}
+
+	/// Mimics the corresponding method in the offchain API.
+	pub fn num_requests(&self) -> usize {
+		0
+	}
 }
 
 impl Future for HttpWorker {
@@ -47,7 +47,12 @@ pub fn timestamp_from_now(timestamp: Timestamp) -> Duration {
 
 /// Converts the deadline into a `Future` that resolves when the deadline is reached.
 ///
-/// If `None`, returns a never-ending `Future`.
+/// If `None`, returns a never-ending `Future`. If the deadline has already passed, the `Future`
+/// resolves immediately.
+///
+/// A single timer is created when calling this function, so the returned `Future` must be kept
+/// and polled in place (e.g. through `&mut` in a `select`) rather than recreated. Dropping a
+/// `select` it is part of doesn't affect it, and once the deadline is reached it stays `Done`.
 pub fn deadline_to_future(
 	deadline: Option<Timestamp>,
 ) -> futures::future::MaybeDone<impl futures::Future> {
@@ -62,3 +67,48 @@ pub fn deadline_to_future(
 			Either::Right(Either::Right(futures_timer::Delay::new(duration))),
 	})
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::{executor::block_on, future::{self, Either, MaybeDone}};
+	use std::time::Instant;
+
+	#[test]
+	fn past_deadline_resolves_immediately() {
+		let deadline = now().sub(sp_core::offchain::Duration::from_millis(1_000));
+		let mut deadline = deadline_to_future(Some(deadline));
+
+		let start = Instant::now();
+		block_on(&mut deadline);
+		assert!(start.elapsed() < Duration::from_millis(500));
+		assert!(matches!(deadline, MaybeDone::Done(_)));
+
+		// The future can be waited upon again.
+		block_on(&mut deadline);
+	}
+
+	#[test]
+	fn near_deadline_resolves_at_deadline() {
+		let deadline = now().add(sp_core::offchain::Duration::from_millis(200));
+		let mut deadline_future = deadline_to_future(Some(deadline));
+
+		// Waiting on the deadline and giving up doesn't affect it.
+		let delay = futures_timer::Delay::new(Duration::from_millis(10));
+		assert!(matches!(block_on(future::select(&mut deadline_future, delay)), Either::Right(_)));
+		assert!(matches!(deadline_future, MaybeDone::Future(_)));
+
+		block_on(&mut deadline_future);
+		assert!(now() >= deadline);
+		assert!(matches!(deadline_future, MaybeDone::Done(_)));
+	}
+
+	#[test]
+	fn no_deadline_never_resolves() {
+		let mut deadline = deadline_to_future(None);
+
+		let delay = futures_timer::Delay::new(Duration::from_millis(100));
+		assert!(matches!(block_on(future::select(&mut deadline, delay)), Either::Right(_)));
+		assert!(matches!(deadline, MaybeDone::Future(_)));
+	}
+}
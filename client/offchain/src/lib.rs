@@ -33,7 +33,7 @@
 
 #![warn(missing_docs)]
 
-use std::{fmt, marker::PhantomData, sync::Arc};
+use std::{fmt, marker::PhantomData, sync::Arc, time::Duration};
 
 use parking_lot::Mutex;
 use threadpool::ThreadPool;
@@ -46,8 +46,17 @@ use sp_runtime::{generic::BlockId, traits::{self, Header}};
 use futures::{prelude::*, future::ready};
 
 mod api;
+#[cfg(any(test, feature = "test-helpers"))]
+pub mod testing;
 use api::SharedClient;
 
+pub use api::{
+	DnsResolver, HttpChannelNames, HttpCircuitBreaker, HttpConfig, HttpConnectionCounts,
+	HttpConnectionMetrics, HttpProtocol, HttpProxy, HttpRateLimit, HttpRecording, HttpRetry,
+	HttpWorkerStatus, ShutdownSignal, SleepCanceller, StaticResolver, StorageCompression,
+	StorageMetrics, StorageOperationCounts, TlsVersion,
+};
+pub use sp_core::offchain::NodeRole;
 pub use sp_offchain::{OffchainWorkerApi, STORAGE_PREFIX};
 
 /// An offchain workers manager.
@@ -57,20 +66,85 @@ pub struct OffchainWorkers<Client, Storage, Block: traits::Block> {
 	_block: PhantomData<Block>,
 	thread_pool: Mutex<ThreadPool>,
 	shared_client: SharedClient,
+	/// Configuration of the offchain API of every block. Its sleep canceller cancels the calls to
+	/// `sleep_until` of the offchain workers of every block.
+	api_config: api::ApiConfig,
+	/// Stops the background processing of the offchain workers of every block.
+	shutdown: ShutdownSignal,
+	/// Health of the HTTP worker of the last block the offchain workers ran for.
+	http_worker_health: Mutex<Option<api::HttpWorkerHealth>>,
 }
 
 impl<Client, Storage, Block: traits::Block> OffchainWorkers<Client, Storage, Block> {
 	/// Creates new `OffchainWorkers`.
 	pub fn new(client: Arc<Client>, db: Storage) -> Self {
-		let shared_client = SharedClient::new();
+		Self::with_http_config(client, db, HttpConfig::default())
+	}
+
+	/// Creates new `OffchainWorkers` whose HTTP requests are handled according to `http_config`.
+	pub fn with_http_config(client: Arc<Client>, db: Storage, http_config: HttpConfig) -> Self {
+		let shared_client = SharedClient::with_config(&http_config);
 		Self {
 			client,
 			db,
 			_block: PhantomData,
 			thread_pool: Mutex::new(ThreadPool::new(num_cpus::get())),
 			shared_client,
+			api_config: api::ApiConfig { http: http_config, ..Default::default() },
+			shutdown: ShutdownSignal::default(),
+			http_worker_health: Mutex::new(None),
 		}
 	}
+
+	/// Limits the duration offchain workers can sleep for in a single call to `sleep_until`.
+	///
+	/// Longer sleeps are cut short, so that a buggy worker can't block its thread forever.
+	/// Defaults to five minutes.
+	pub fn with_max_sleep(mut self, max_sleep: Duration) -> Self {
+		self.api_config.max_sleep = max_sleep;
+		self
+	}
+
+	/// Counts the offchain storage operations of the offchain workers in `metrics`.
+	///
+	/// The operations aren't counted by default.
+	pub fn with_storage_metrics(mut self, metrics: StorageMetrics) -> Self {
+		self.api_config.storage_metrics = Some(metrics);
+		self
+	}
+
+	/// Compresses the values offchain workers write to the persistent offchain storage according
+	/// to `compression`, and decompresses them when they are read.
+	///
+	/// The values aren't compressed by default.
+	pub fn with_storage_compression(mut self, compression: StorageCompression) -> Self {
+		self.api_config.storage_compression = Some(compression);
+		self
+	}
+
+	/// Returns a handle cancelling the calls to `sleep_until` in progress in the offchain workers
+	/// of every block, e.g. to quickly shut them down.
+	pub fn sleep_canceller(&self) -> SleepCanceller {
+		self.api_config.sleep_canceller.clone()
+	}
+
+	/// Returns a signal stopping the background processing, e.g. the HTTP worker, of the offchain
+	/// workers of every block at once.
+	///
+	/// The futures returned by `on_block_imported` resolve once it is signalled, and those returned
+	/// afterwards resolve right away.
+	pub fn shutdown_signal(&self) -> ShutdownSignal {
+		self.shutdown.clone()
+	}
+
+	/// Returns the status of the HTTP worker of the last block the offchain workers ran for, or
+	/// `None` if they haven't run yet.
+	///
+	/// Each block has its own HTTP worker, which stops once the offchain workers of the block are
+	/// done.
+	pub fn http_worker_status(&self) -> Option<HttpWorkerStatus> {
+		self.http_worker_health.lock().as_ref().map(|health| health.status())
+	}
 }
 
 impl<Client, Storage, Block: traits::Block> fmt::Debug for OffchainWorkers<
@@ -99,7 +173,7 @@ impl<Client, Storage, Block> OffchainWorkers<
 		&self,
 		header: &Block::Header,
 		network_state: Arc<dyn NetworkStateInfo + Send + Sync>,
-		is_validator: bool,
+		node_role: NodeRole,
 	) -> impl Future<Output = ()> {
 		let runtime = self.client.runtime_api();
 		let at = BlockId::hash(header.hash());
@@ -123,9 +197,11 @@ impl<Client, Storage, Block> OffchainWorkers<
 			let (api, runner) = api::AsyncApi::new(
 				self.db.clone(),
 				network_state.clone(),
-				is_validator,
+				node_role,
 				self.shared_client.clone(),
+				self.api_config.clone(),
 			);
+			*self.http_worker_health.lock() = Some(runner.http_worker_health());
 			debug!("Spawning offchain workers at {:?}", at);
 			let header = header.clone();
 			let client = self.client.clone();
@@ -148,7 +224,7 @@ impl<Client, Storage, Block> OffchainWorkers<
 					log::error!("Error running offchain workers at {:?}: {:?}", at, e);
 				}
 			});
-			futures::future::Either::Left(runner.process())
+			futures::future::Either::Left(runner.process(self.shutdown.clone()))
 		} else {
 			futures::future::Either::Right(futures::future::ready(()))
 		}
@@ -169,7 +245,7 @@ impl<Client, Storage, Block> OffchainWorkers<
 
 /// Inform the offchain worker about new imported blocks
 pub async fn notification_future<Client, Storage, Block, Spawner>(
-	is_validator: bool,
+	node_role: NodeRole,
 	client: Arc<Client>,
 	offchain: Arc<OffchainWorkers<Client, Storage, Block>>,
 	spawner: Spawner,
@@ -189,7 +265,7 @@ pub async fn notification_future<Client, Storage, Block, Spawner>(
 				offchain.on_block_imported(
 					&n.header,
 					network_state_info.clone(),
-					is_validator,
+					node_role,
 				).boxed(),
 			);
 		} else {
@@ -220,9 +296,17 @@ mod tests {
 			Vec::new()
 		}
 
+		fn listen_addresses(&self) -> Vec<Multiaddr> {
+			Vec::new()
+		}
+
 		fn local_peer_id(&self) -> PeerId {
 			PeerId::random()
 		}
+
+		fn num_connected_peers(&self) -> usize {
+			0
+		}
 	}
 
 	struct TestPool(
@@ -244,7 +328,7 @@ mod tests {
 
 	#[test]
 	fn should_call_into_runtime_and_produce_extrinsic() {
-		let _ = env_logger::try_init();
+		api::tests::init_logger();
 
 		let client = Arc::new(substrate_test_runtime_client::new());
 		let spawner = sp_core::testing::TaskExecutor::new();
@@ -260,7 +344,9 @@ mod tests {
 
 		// when
 		let offchain = OffchainWorkers::new(client, db);
-		futures::executor::block_on(offchain.on_block_imported(&header, network_state, false));
+		futures::executor::block_on(
+			offchain.on_block_imported(&header, network_state, NodeRole::Full)
+		);
 
 		// then
 		assert_eq!(pool.0.status().ready, 1);
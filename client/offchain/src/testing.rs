@@ -0,0 +1,85 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Testing utils for the offchain workers.
+
+use sc_network::{config::identity::{ed25519, Keypair}, Multiaddr, NetworkStateInfo, PeerId};
+
+/// Network state with a fixed peer id and addresses, so that the network state seen by offchain
+/// workers, e.g. through `AsyncApi::new`, is the same from one run to the next.
+#[derive(Clone, Debug)]
+pub struct FixedNetworkState {
+	peer_id: PeerId,
+	external_addresses: Vec<Multiaddr>,
+	listen_addresses: Vec<Multiaddr>,
+	connected_peers: usize,
+}
+
+impl FixedNetworkState {
+	/// Creates a network state with the given peer id, no addresses and no connected peers.
+	pub fn new(peer_id: PeerId) -> Self {
+		FixedNetworkState {
+			peer_id,
+			external_addresses: Vec::new(),
+			listen_addresses: Vec::new(),
+			connected_peers: 0,
+		}
+	}
+
+	/// Creates a network state with no addresses and no connected peers, whose peer id is derived
+	/// from an ed25519 key made of `seed` repeated.
+	pub fn from_seed(seed: u8) -> Self {
+		let secret = ed25519::SecretKey::from_bytes([seed; 32])
+			.expect("any 32 bytes are a valid ed25519 secret key; qed");
+		Self::new(Keypair::Ed25519(secret.into()).public().into_peer_id())
+	}
+
+	/// Sets the external addresses of the node.
+	pub fn with_external_addresses(mut self, addresses: Vec<Multiaddr>) -> Self {
+		self.external_addresses = addresses;
+		self
+	}
+
+	/// Sets the addresses the node listens on.
+	pub fn with_listen_addresses(mut self, addresses: Vec<Multiaddr>) -> Self {
+		self.listen_addresses = addresses;
+		self
+	}
+
+	/// Sets the number of peers the node is connected to.
+	pub fn with_connected_peers(mut self, connected_peers: usize) -> Self {
+		self.connected_peers = connected_peers;
+		self
+	}
+}
+
+impl NetworkStateInfo for FixedNetworkState {
+	fn external_addresses(&self) -> Vec<Multiaddr> {
+		self.external_addresses.clone()
+	}
+
+	fn listen_addresses(&self) -> Vec<Multiaddr> {
+		self.listen_addresses.clone()
+	}
+
+	fn local_peer_id(&self) -> PeerId {
+		self.peer_id.clone()
+	}
+
+	fn num_connected_peers(&self) -> usize {
+		self.connected_peers
+	}
+}
@@ -424,10 +424,15 @@ pub fn build_offchain_workers<TBl, TBackend, TCl>(
 
 	// Inform the offchain worker about new imported blocks
 	if let Some(offchain) = offchain_workers.clone() {
+		let node_role = match config.role {
+			Role::Authority { .. } => sc_offchain::NodeRole::Authority,
+			Role::Light => sc_offchain::NodeRole::Light,
+			Role::Full | Role::Sentry { .. } => sc_offchain::NodeRole::Full,
+		};
 		spawn_handle.spawn(
 			"offchain-notifications",
 			sc_offchain::notification_future(
-				config.role.is_authority(),
+				node_role,
 				client.clone(),
 				offchain,
 				Clone::clone(&spawn_handle),
@@ -22,6 +22,7 @@
 use super::*;
 use crate::mock::*;
 use sp_core::offchain::{
+	OpaqueMultiaddr,
 	OpaquePeerId,
 	OffchainExt,
 	TransactionPoolExt,
@@ -354,3 +355,29 @@ fn should_not_send_a_report_if_already_online() {
 		});
 	});
 }
+
+#[test]
+fn legacy_heartbeat_round_trips() {
+	// heartbeat as encoded, and signed, by nodes whose network state only has a peer id and
+	// external addresses, followed by the fields after it.
+	let peer_id = OpaquePeerId(vec![1]);
+	let external_addresses = vec![OpaqueMultiaddr::new(vec![2, 3])];
+	let legacy = (4u64, (&peer_id, &external_addresses), 2u32, 1u32, 3u32).encode();
+
+	let mut input = &legacy[..];
+	let heartbeat = Heartbeat::<u64>::decode(&mut input).unwrap();
+	assert!(input.is_empty());
+	assert_eq!(heartbeat, Heartbeat {
+		block_number: 4,
+		network_state: OpaqueNetworkState { peer_id, external_addresses },
+		session_index: 2,
+		authority_index: 1,
+		validators_len: 3,
+	});
+
+	// the signature of the legacy encoding checks out against the decoded heartbeat.
+	let id = UintAuthorityId(1);
+	let signature = id.sign(&legacy).unwrap();
+	assert_eq!(heartbeat.encode(), legacy);
+	assert!(id.verify(&heartbeat.encode(), &signature));
+}
@@ -33,6 +33,11 @@ pub mod testing;
 pub const STORAGE_PREFIX : &'static [u8] = b"storage";
 
 /// Offchain workers local storage.
+///
+/// Writes are not buffered: a value is persisted by the time `set`, `remove` or
+/// `compare_and_set` returns, so there is nothing to flush when the storage is dropped on a
+/// graceful shutdown. Whether a value survives an abrupt kill of the process is up to the
+/// backend.
 pub trait OffchainStorage: Clone + Send + Sync {
 	/// Persist a value in storage under given key and prefix.
 	fn set(&mut self, prefix: &[u8], key: &[u8], value: &[u8]);
@@ -43,6 +48,14 @@ pub trait OffchainStorage: Clone + Send + Sync {
 	/// Retrieve a value from storage under given key and prefix.
 	fn get(&self, prefix: &[u8], key: &[u8]) -> Option<Vec<u8>>;
 
+	/// Retrieve the length of a value in storage under given key and prefix.
+	///
+	/// The default implementation reads the whole value; backends that can tell the length
+	/// without copying the value should override it.
+	fn len(&self, prefix: &[u8], key: &[u8]) -> Option<usize> {
+		self.get(prefix, key).map(|value| value.len())
+	}
+
 	/// Replace the value in storage if given old_value matches the current one.
 	///
 	/// Returns `true` if the value has been set and false otherwise.
@@ -179,6 +192,15 @@ impl TryFrom<u32> for HttpRequestStatus {
 	}
 }
 
+/// Number of body bytes transferred by an HTTP request.
+#[derive(Clone, Copy, Default, PartialEq, Eq, RuntimeDebug, Encode, Decode)]
+pub struct HttpStats {
+	/// Number of bytes of the request body that have been sent.
+	pub bytes_sent: u64,
+	/// Number of bytes of the response body that have been received.
+	pub bytes_received: u64,
+}
+
 /// A blob to hold information about the local node's network state
 /// without committing to its format.
 #[derive(Clone, Eq, PartialEq, Encode, Decode, RuntimeDebug, PassByCodec)]
@@ -259,6 +281,26 @@ impl Timestamp {
 	}
 }
 
+/// Role of the local node, as reported to offchain workers.
+#[derive(Clone, Copy, PartialEq, Eq, Encode, Decode, RuntimeDebug)]
+pub enum NodeRole {
+	/// The node is a potential validator.
+	Authority,
+	/// Regular full node.
+	Full,
+	/// Light client.
+	Light,
+}
+
+/// Type of the DNS records looked up by offchain workers.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Encode, Decode, RuntimeDebug)]
+pub enum DnsRecordType {
+	/// IPv4 addresses, as 4 bytes each.
+	A,
+	/// Text records, as the bytes of each record.
+	Txt,
+}
+
 /// Execution context extra capabilities.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 #[repr(u8)]
@@ -324,7 +366,25 @@ impl<'a> From<&'a [Capability]> for Capabilities {
 }
 
 /// An extended externalities for offchain workers.
+///
+/// `node_role`, `network_listen_addresses`, `local_storage_len`, `http_request_ids`,
+/// `http_response_wait_any`, `http_response_trailers`, `http_response_stats` and `dns_query`
+/// aren't exposed to the runtime through `sp_io::offchain`: they are only meant for node-side
+/// code driving offchain workers. They have default implementations, so that existing
+/// implementations of the trait don't have to provide them.
 pub trait Externalities: Send {
+	/// Returns the role of the local node.
+	///
+	/// By default, the node is an authority if it is a potential validator, and a full node
+	/// otherwise.
+	fn node_role(&self) -> NodeRole {
+		if self.is_validator() {
+			NodeRole::Authority
+		} else {
+			NodeRole::Full
+		}
+	}
+
 	/// Returns if the local node is a potential validator.
 	///
 	/// Even if this function returns `true`, it does not mean that any keys are configured
@@ -334,6 +394,16 @@ pub trait Externalities: Send {
 	/// Returns information about the local node's network state.
 	fn network_state(&self) -> Result<OpaqueNetworkState, ()>;
 
+	/// Returns the addresses the local node is listening on.
+	///
+	/// These aren't part of `network_state`, whose encoding is relied upon by signed payloads
+	/// such as heartbeats and must stay the same.
+	///
+	/// Fails by default.
+	fn network_listen_addresses(&self) -> Result<Vec<OpaqueMultiaddr>, ()> {
+		Err(())
+	}
+
 	/// Returns current UNIX timestamp (in millis)
 	fn timestamp(&mut self) -> Timestamp;
 
@@ -382,6 +452,44 @@ pub trait Externalities: Send {
 	/// offchain worker tasks running on the same machine. It _is_ persisted between runs.
 	fn local_storage_get(&mut self, kind: StorageKind, key: &[u8]) -> Option<Vec<u8>>;
 
+	/// Gets the length of a value in the local storage.
+	///
+	/// If the value does not exist in the storage `None` will be returned. Unlike
+	/// `local_storage_get`, the value isn't copied if the storage can tell its length without
+	/// reading it.
+	fn local_storage_len(&mut self, kind: StorageKind, key: &[u8]) -> Option<usize> {
+		self.local_storage_get(kind, key).map(|value| value.len())
+	}
+
+	/// Atomically updates a value in the local storage.
+	///
+	/// Reads the current value, computes the new one with `f` and writes it back using
+	/// `local_storage_compare_and_set`. If another task has changed the value in the meantime,
+	/// the whole operation is tried again, at most `max_retries` times.
+	///
+	/// Returns `true` if the new value has been written, `false` if every attempt lost the race.
+	fn local_storage_mutate<F>(
+		&mut self,
+		kind: StorageKind,
+		key: &[u8],
+		max_retries: u32,
+		f: F,
+	) -> bool
+		where
+			F: Fn(Option<Vec<u8>>) -> Vec<u8>,
+			Self: Sized,
+	{
+		for _ in 0..=max_retries {
+			let old_value = self.local_storage_get(kind, key);
+			let new_value = f(old_value.clone());
+			if self.local_storage_compare_and_set(kind, key, old_value.as_deref(), &new_value) {
+				return true
+			}
+		}
+
+		false
+	}
+
 	/// Initiates a http request given HTTP verb and the URL.
 	///
 	/// Meta is a future-reserved field containing additional, parity-scale-codec encoded parameters.
@@ -440,9 +548,22 @@ pub trait Externalities: Send {
 		deadline: Option<Timestamp>
 	) -> Result<(), HttpError>;
 
+	/// Returns the ids of the requests that are still in progress, in increasing order.
+	///
+	/// Requests are in progress from the moment they are started until their response has been
+	/// read entirely, or until they are reported as failed. This allows to find requests whose id
+	/// has been lost, e.g. to wait for them before shutting down.
+	///
+	/// Returns an empty list by default.
+	fn http_request_ids(&self) -> Vec<HttpRequestId> {
+		Vec::new()
+	}
+
 	/// Block and wait for the responses for given requests.
 	///
 	/// Returns a vector of request statuses (the len is the same as ids).
+	/// The statuses are in the same order as `ids`, regardless of the order in which the
+	/// requests complete.
 	/// Note that if deadline is not provided the method will block indefinitely,
 	/// otherwise unready responses will produce `DeadlineReached` status.
 	///
@@ -456,6 +577,24 @@ pub trait Externalities: Send {
 		deadline: Option<Timestamp>
 	) -> Vec<HttpRequestStatus>;
 
+	/// Block and wait for the first of the given requests to complete.
+	///
+	/// Returns the id and status of the first request that either received a response, failed
+	/// or is invalid. The other requests are left in flight and can be waited upon again.
+	/// Returns `None` if the deadline is reached before any request completes, or if `ids` is
+	/// empty.
+	///
+	/// Passing `None` as deadline blocks until a request completes.
+	///
+	/// By default, returns `None` right away, as if no request had completed.
+	fn http_response_wait_any(
+		&mut self,
+		_ids: &[HttpRequestId],
+		_deadline: Option<Timestamp>
+	) -> Option<(HttpRequestId, HttpRequestStatus)> {
+		None
+	}
+
 	/// Read all response headers.
 	///
 	/// Returns a vector of pairs `(HeaderKey, HeaderValue)`.
@@ -470,6 +609,20 @@ pub trait Externalities: Send {
 		request_id: HttpRequestId
 	) -> Vec<(Vec<u8>, Vec<u8>)>;
 
+	/// Read all response trailers.
+	///
+	/// Returns a vector of pairs `(HeaderKey, HeaderValue)`.
+	///
+	/// Trailers are only available once the response body has been read entirely. Returns an
+	/// empty list if the identifier is unknown/invalid, the body hasn't been read entirely yet,
+	/// or the response doesn't have any trailers, which is the default.
+	fn http_response_trailers(
+		&mut self,
+		_request_id: HttpRequestId
+	) -> Vec<(Vec<u8>, Vec<u8>)> {
+		Vec::new()
+	}
+
 	/// Read a chunk of body response to given buffer.
 	///
 	/// Dispatches the request if it hasn't been done yet. It is no longer
@@ -479,13 +632,18 @@ pub trait Externalities: Send {
 	/// is reached or server closed the connection.
 	/// Passing `None` as a deadline blocks forever.
 	///
+	/// `Ok(0)` is only returned once the body has been read entirely, and marks
+	/// its end. A read waits until some data is available, so when no data has
+	/// arrived yet, the read ends with `Err(DeadlineReached)` instead, and the
+	/// body can be read further later on.
+	///
 	/// If `Ok(0)` or `Err(IoError)` is returned, the request is considered
 	/// destroyed. Doing another read or getting the response's headers, for
 	/// example, is then invalid.
 	///
 	/// Returns an error if:
-	/// - The request identifier is invalid.
-	/// - The deadline is reached.
+	/// - The request identifier is invalid, or `buffer` is empty.
+	/// - The deadline is reached. The request stays valid.
 	/// - An I/O error has happened, for example the remote has closed our
 	///   request. The request is then considered invalid.
 	///
@@ -495,9 +653,33 @@ pub trait Externalities: Send {
 		buffer: &mut [u8],
 		deadline: Option<Timestamp>
 	) -> Result<usize, HttpError>;
+
+	/// Returns the number of body bytes sent and received by a finished request.
+	///
+	/// A request is finished once its response body has been read to the end.
+	///
+	/// Returns `None` if the identifier is unknown/invalid, or if the request hasn't finished
+	/// yet. Returns `None` by default.
+	fn http_response_stats(&mut self, _request_id: HttpRequestId) -> Option<HttpStats> {
+		None
+	}
+
+	/// Looks up the DNS records of `name` of the given type.
+	///
+	/// A name without any record of that type has an empty list of records.
+	///
+	/// Returns an error if the node doesn't support DNS queries, which is the default, or doesn't
+	/// support looking up records of that type.
+	fn dns_query(&mut self, _name: &str, _record_type: DnsRecordType) -> Result<Vec<Vec<u8>>, ()> {
+		Err(())
+	}
 }
 
 impl<T: Externalities + ?Sized> Externalities for Box<T> {
+	fn node_role(&self) -> NodeRole {
+		(& **self).node_role()
+	}
+
 	fn is_validator(&self) -> bool {
 		(& **self).is_validator()
 	}
@@ -506,6 +688,10 @@ impl<T: Externalities + ?Sized> Externalities for Box<T> {
 		(& **self).network_state()
 	}
 
+	fn network_listen_addresses(&self) -> Result<Vec<OpaqueMultiaddr>, ()> {
+		(& **self).network_listen_addresses()
+	}
+
 	fn timestamp(&mut self) -> Timestamp {
 		(&mut **self).timestamp()
 	}
@@ -540,6 +726,10 @@ impl<T: Externalities + ?Sized> Externalities for Box<T> {
 		(&mut **self).local_storage_get(kind, key)
 	}
 
+	fn local_storage_len(&mut self, kind: StorageKind, key: &[u8]) -> Option<usize> {
+		(&mut **self).local_storage_len(kind, key)
+	}
+
 	fn http_request_start(&mut self, method: &str, uri: &str, meta: &[u8]) -> Result<HttpRequestId, ()> {
 		(&mut **self).http_request_start(method, uri, meta)
 	}
@@ -548,6 +738,10 @@ impl<T: Externalities + ?Sized> Externalities for Box<T> {
 		(&mut **self).http_request_add_header(request_id, name, value)
 	}
 
+	fn http_request_ids(&self) -> Vec<HttpRequestId> {
+		(&**self).http_request_ids()
+	}
+
 	fn http_request_write_body(
 		&mut self,
 		request_id: HttpRequestId,
@@ -561,10 +755,22 @@ impl<T: Externalities + ?Sized> Externalities for Box<T> {
 		(&mut **self).http_response_wait(ids, deadline)
 	}
 
+	fn http_response_wait_any(
+		&mut self,
+		ids: &[HttpRequestId],
+		deadline: Option<Timestamp>
+	) -> Option<(HttpRequestId, HttpRequestStatus)> {
+		(&mut **self).http_response_wait_any(ids, deadline)
+	}
+
 	fn http_response_headers(&mut self, request_id: HttpRequestId) -> Vec<(Vec<u8>, Vec<u8>)> {
 		(&mut **self).http_response_headers(request_id)
 	}
 
+	fn http_response_trailers(&mut self, request_id: HttpRequestId) -> Vec<(Vec<u8>, Vec<u8>)> {
+		(&mut **self).http_response_trailers(request_id)
+	}
+
 	fn http_response_read_body(
 		&mut self,
 		request_id: HttpRequestId,
@@ -573,6 +779,14 @@ impl<T: Externalities + ?Sized> Externalities for Box<T> {
 	) -> Result<usize, HttpError> {
 		(&mut **self).http_response_read_body(request_id, buffer, deadline)
 	}
+
+	fn http_response_stats(&mut self, request_id: HttpRequestId) -> Option<HttpStats> {
+		(&mut **self).http_response_stats(request_id)
+	}
+
+	fn dns_query(&mut self, name: &str, record_type: DnsRecordType) -> Result<Vec<Vec<u8>>, ()> {
+		(&mut **self).dns_query(name, record_type)
+	}
 }
 
 /// An `OffchainExternalities` implementation with limited capabilities.
@@ -601,6 +815,11 @@ impl<T> LimitedExternalities<T> {
 }
 
 impl<T: Externalities> Externalities for LimitedExternalities<T> {
+	fn node_role(&self) -> NodeRole {
+		self.check(Capability::Keystore, "node_role");
+		self.externalities.node_role()
+	}
+
 	fn is_validator(&self) -> bool {
 		self.check(Capability::Keystore, "is_validator");
 		self.externalities.is_validator()
@@ -611,6 +830,11 @@ impl<T: Externalities> Externalities for LimitedExternalities<T> {
 		self.externalities.network_state()
 	}
 
+	fn network_listen_addresses(&self) -> Result<Vec<OpaqueMultiaddr>, ()> {
+		self.check(Capability::NetworkState, "network_listen_addresses");
+		self.externalities.network_listen_addresses()
+	}
+
 	fn timestamp(&mut self) -> Timestamp {
 		self.check(Capability::Http, "timestamp");
 		self.externalities.timestamp()
@@ -652,6 +876,11 @@ impl<T: Externalities> Externalities for LimitedExternalities<T> {
 		self.externalities.local_storage_get(kind, key)
 	}
 
+	fn local_storage_len(&mut self, kind: StorageKind, key: &[u8]) -> Option<usize> {
+		self.check(Capability::OffchainWorkerDbRead, "local_storage_len");
+		self.externalities.local_storage_len(kind, key)
+	}
+
 	fn http_request_start(&mut self, method: &str, uri: &str, meta: &[u8]) -> Result<HttpRequestId, ()> {
 		self.check(Capability::Http, "http_request_start");
 		self.externalities.http_request_start(method, uri, meta)
@@ -662,6 +891,11 @@ impl<T: Externalities> Externalities for LimitedExternalities<T> {
 		self.externalities.http_request_add_header(request_id, name, value)
 	}
 
+	fn http_request_ids(&self) -> Vec<HttpRequestId> {
+		self.check(Capability::Http, "http_request_ids");
+		self.externalities.http_request_ids()
+	}
+
 	fn http_request_write_body(
 		&mut self,
 		request_id: HttpRequestId,
@@ -677,11 +911,25 @@ impl<T: Externalities> Externalities for LimitedExternalities<T> {
 		self.externalities.http_response_wait(ids, deadline)
 	}
 
+	fn http_response_wait_any(
+		&mut self,
+		ids: &[HttpRequestId],
+		deadline: Option<Timestamp>
+	) -> Option<(HttpRequestId, HttpRequestStatus)> {
+		self.check(Capability::Http, "http_response_wait_any");
+		self.externalities.http_response_wait_any(ids, deadline)
+	}
+
 	fn http_response_headers(&mut self, request_id: HttpRequestId) -> Vec<(Vec<u8>, Vec<u8>)> {
 		self.check(Capability::Http, "http_response_headers");
 		self.externalities.http_response_headers(request_id)
 	}
 
+	fn http_response_trailers(&mut self, request_id: HttpRequestId) -> Vec<(Vec<u8>, Vec<u8>)> {
+		self.check(Capability::Http, "http_response_trailers");
+		self.externalities.http_response_trailers(request_id)
+	}
+
 	fn http_response_read_body(
 		&mut self,
 		request_id: HttpRequestId,
@@ -691,6 +939,16 @@ impl<T: Externalities> Externalities for LimitedExternalities<T> {
 		self.check(Capability::Http, "http_response_read_body");
 		self.externalities.http_response_read_body(request_id, buffer, deadline)
 	}
+
+	fn http_response_stats(&mut self, request_id: HttpRequestId) -> Option<HttpStats> {
+		self.check(Capability::Http, "http_response_stats");
+		self.externalities.http_response_stats(request_id)
+	}
+
+	fn dns_query(&mut self, name: &str, record_type: DnsRecordType) -> Result<Vec<Vec<u8>>, ()> {
+		self.check(Capability::Http, "dns_query");
+		self.externalities.dns_query(name, record_type)
+	}
 }
 
 #[cfg(feature = "std")]
@@ -61,6 +61,11 @@ impl OffchainStorage for InMemOffchainStorage {
 		self.storage.get(&key).cloned()
 	}
 
+	fn len(&self, prefix: &[u8], key: &[u8]) -> Option<usize> {
+		let key: Vec<u8> = prefix.iter().chain(key).cloned().collect();
+		self.storage.get(&key).map(Vec::len)
+	}
+
 	fn compare_and_set(
 		&mut self,
 		prefix: &[u8],
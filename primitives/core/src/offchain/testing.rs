@@ -27,11 +27,14 @@ use std::{
 use crate::offchain::{
 	self,
 	storage::{InMemOffchainStorage, OffchainOverlayedChange, OffchainOverlayedChanges},
+	DnsRecordType,
 	HttpError,
 	HttpRequestId as RequestId,
 	HttpRequestStatus as RequestStatus,
+	HttpStats,
 	Timestamp,
 	StorageKind,
+	OpaqueMultiaddr,
 	OpaqueNetworkState,
 	TransactionPool,
 	OffchainStorage,
@@ -60,6 +63,8 @@ pub struct PendingRequest {
 	pub read: usize,
 	/// Response headers
 	pub response_headers: Vec<(String, String)>,
+	/// Response trailers
+	pub response_trailers: Vec<(String, String)>,
 }
 
 /// Sharable "persistent" offchain storage for test.
@@ -101,6 +106,10 @@ impl OffchainStorage for TestPersistentOffchainDB {
 		self.persistent.read().get(prefix, key)
 	}
 
+	fn len(&self, prefix: &[u8], key: &[u8]) -> Option<usize> {
+		self.persistent.read().len(prefix, key)
+	}
+
 	fn compare_and_set(
 		&mut self,
 		prefix: &[u8],
@@ -130,6 +139,8 @@ pub struct OffchainState {
 	pub seed: [u8; 32],
 	/// A timestamp simulating the current time.
 	pub timestamp: Timestamp,
+	/// DNS records, by name and type. Names without records have none.
+	pub dns_records: BTreeMap<(String, DnsRecordType), Vec<Vec<u8>>>,
 }
 
 impl OffchainState {
@@ -160,7 +171,11 @@ impl OffchainState {
 		if let Some(mut req) = self.expected_requests.pop_back() {
 			let response = req.response.take().expect("Response checked when added.");
 			let headers = std::mem::take(&mut req.response_headers);
+			let trailers = std::mem::take(&mut req.response_trailers);
 			self.fulfill_pending_request(id, req, response, headers);
+			if let Some(req) = self.requests.get_mut(&RequestId(id)) {
+				req.response_trailers = trailers;
+			}
 		}
 	}
 
@@ -169,7 +184,8 @@ impl OffchainState {
 	/// This method can be used to initialize expected HTTP requests and their responses
 	/// before running the actual code that utilizes them (for instance before calling into runtime).
 	/// Expected request has to be fulfilled before this struct is dropped,
-	/// the `response` and `response_headers` fields will be used to return results to the callers.
+	/// the `response`, `response_headers` and `response_trailers` fields will be used to return
+	/// results to the callers.
 	/// Requests are expected to be performed in the insertion order.
 	pub fn expect_request(&mut self, expected: PendingRequest) {
 		if expected.response.is_none() {
@@ -209,6 +225,10 @@ impl TestOffchainExt {
 }
 
 impl offchain::Externalities for TestOffchainExt {
+	fn node_role(&self) -> offchain::NodeRole {
+		offchain::NodeRole::Authority
+	}
+
 	fn is_validator(&self) -> bool {
 		true
 	}
@@ -220,6 +240,10 @@ impl offchain::Externalities for TestOffchainExt {
 		})
 	}
 
+	fn network_listen_addresses(&self) -> Result<Vec<OpaqueMultiaddr>, ()> {
+		Ok(vec![])
+	}
+
 	fn timestamp(&mut self) -> Timestamp {
 		self.0.read().timestamp
 	}
@@ -270,6 +294,14 @@ impl offchain::Externalities for TestOffchainExt {
 		}
 	}
 
+	fn local_storage_len(&mut self, kind: StorageKind, key: &[u8]) -> Option<usize> {
+		let state = self.0.read();
+		match kind {
+			StorageKind::LOCAL => state.local_storage.len(b"", key),
+			StorageKind::PERSISTENT => state.persistent_storage.len(b"", key),
+		}
+	}
+
 	fn http_request_start(&mut self, method: &str, uri: &str, meta: &[u8]) -> Result<RequestId, ()> {
 		let mut state = self.0.write();
 		let id = RequestId(state.requests.len() as u16);
@@ -297,6 +329,15 @@ impl offchain::Externalities for TestOffchainExt {
 		}
 	}
 
+	fn http_request_ids(&self) -> Vec<RequestId> {
+		// Requests whose response has been read entirely are over, even if the final read that
+		// removes them hasn't happened yet.
+		self.0.read().requests.iter()
+			.filter(|(_, req)| req.response.as_ref().map_or(true, |r| req.read < r.len()))
+			.map(|(id, _)| *id)
+			.collect()
+	}
+
 	fn http_request_write_body(
 		&mut self,
 		request_id: RequestId,
@@ -336,6 +377,16 @@ impl offchain::Externalities for TestOffchainExt {
 		}).collect()
 	}
 
+	fn http_response_wait_any(
+		&mut self,
+		ids: &[RequestId],
+		deadline: Option<Timestamp>,
+	) -> Option<(RequestId, RequestStatus)> {
+		// All the expected requests complete immediately, so the first one always wins.
+		let id = *ids.first()?;
+		self.http_response_wait(&[id], deadline).pop().map(|status| (id, status))
+	}
+
 	fn http_response_headers(&mut self, request_id: RequestId) -> Vec<(Vec<u8>, Vec<u8>)> {
 		let state = self.0.read();
 		if let Some(req) = state.requests.get(&request_id) {
@@ -349,12 +400,28 @@ impl offchain::Externalities for TestOffchainExt {
 		}
 	}
 
+	fn http_response_trailers(&mut self, request_id: RequestId) -> Vec<(Vec<u8>, Vec<u8>)> {
+		let state = self.0.read();
+		match state.requests.get(&request_id) {
+			Some(req) if req.response.as_ref().map_or(false, |r| req.read >= r.len()) =>
+				req.response_trailers
+					.iter()
+					.map(|(k, v)| (k.clone().into_bytes(), v.clone().into_bytes()))
+					.collect(),
+			_ => Default::default(),
+		}
+	}
+
 	fn http_response_read_body(
 		&mut self,
 		request_id: RequestId,
 		buffer: &mut [u8],
 		_deadline: Option<Timestamp>
 	) -> Result<usize, HttpError> {
+		if buffer.is_empty() {
+			return Err(HttpError::Invalid)
+		}
+
 		let mut state = self.0.write();
 		if let Some(req) = state.requests.get_mut(&request_id) {
 			let response = req.response
@@ -367,7 +434,7 @@ impl offchain::Externalities for TestOffchainExt {
 				Ok(0)
 			} else {
 				let read = std::cmp::min(buffer.len(), response[req.read..].len());
-				buffer[0..read].copy_from_slice(&response[req.read..read]);
+				buffer[0..read].copy_from_slice(&response[req.read..req.read + read]);
 				req.read += read;
 				Ok(read)
 			}
@@ -375,6 +442,25 @@ impl offchain::Externalities for TestOffchainExt {
 			Err(HttpError::IoError)
 		}
 	}
+
+	fn http_response_stats(&mut self, request_id: RequestId) -> Option<HttpStats> {
+		let state = self.0.read();
+		let req = state.requests.get(&request_id)?;
+		let response = req.response.as_ref()?;
+		if req.read >= response.len() {
+			Some(HttpStats {
+				bytes_sent: req.body.len() as u64,
+				bytes_received: response.len() as u64,
+			})
+		} else {
+			None
+		}
+	}
+
+	fn dns_query(&mut self, name: &str, record_type: DnsRecordType) -> Result<Vec<Vec<u8>>, ()> {
+		let state = self.0.read();
+		Ok(state.dns_records.get(&(name.to_owned(), record_type)).cloned().unwrap_or_default())
+	}
 }
 
 /// The internal state of the fake transaction pool.
@@ -411,3 +497,32 @@ impl TransactionPool for TestTransactionPoolExt {
 		Ok(())
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::offchain::Externalities;
+
+	#[test]
+	fn request_ids_only_lists_requests_in_progress() {
+		let (mut ext, state) = TestOffchainExt::new();
+		state.write().expect_request(PendingRequest {
+			method: "GET".into(),
+			uri: "http://localhost:1234".into(),
+			sent: true,
+			response: Some(b"hello".to_vec()),
+			..Default::default()
+		});
+
+		let finished = ext.http_request_start("GET", "http://localhost:1234", &[]).unwrap();
+		let pending = ext.http_request_start("POST", "http://localhost:1234", &[]).unwrap();
+		ext.http_request_write_body(finished, &[], None).unwrap();
+		assert_eq!(ext.http_request_ids(), vec![finished, pending]);
+
+		let mut buffer = [0; 64];
+		assert_eq!(ext.http_response_read_body(finished, &mut buffer, None), Ok(5));
+		assert_eq!(ext.http_request_ids(), vec![pending]);
+		assert_eq!(ext.http_response_read_body(finished, &mut buffer, None), Ok(0));
+		assert_eq!(ext.http_request_ids(), vec![pending]);
+	}
+}
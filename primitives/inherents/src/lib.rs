@@ -153,6 +153,13 @@ impl InherentData {
 	pub fn len(&self) -> usize {
 		self.data.len()
 	}
+
+	/// Merge the data of `other` into this instance.
+	///
+	/// For inherents that are present in both, the data of `other` replaces the data of `self`.
+	pub fn merge(&mut self, other: InherentData) {
+		self.data.extend(other.data);
+	}
 }
 
 /// The result of checking inherents.
@@ -917,6 +917,8 @@ pub trait Offchain {
 	/// Block and wait for the responses for given requests.
 	///
 	/// Returns a vector of request statuses (the len is the same as ids).
+	/// The statuses are in the same order as `ids`, regardless of the order in which the
+	/// requests complete.
 	/// Note that if deadline is not provided the method will block indefinitely,
 	/// otherwise unready responses will produce `DeadlineReached` status.
 	///
@@ -946,7 +948,9 @@ pub trait Offchain {
 	/// Returns the number of bytes written or an error in case a deadline
 	/// is reached or server closed the connection.
 	/// If `0` is returned it means that the response has been fully consumed
-	/// and the `request_id` is now invalid.
+	/// and the `request_id` is now invalid. A body that has no data available
+	/// yet makes the read fail with `DeadlineReached` instead, and can be read
+	/// again later. Reading into an empty buffer is invalid.
 	/// NOTE this implies that response headers must be read before draining the body.
 	/// Passing `None` as a deadline blocks forever.
 	fn http_response_read_body(